@@ -0,0 +1,354 @@
+use crate::client::{ClientError, RegistryClient};
+use crate::config::Context;
+use crate::models::{Application, Device};
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use colored::Colorize;
+use serde::Deserialize;
+use serde_json::{from_value, to_value, Value};
+use similar::TextDiff;
+use std::fs;
+use std::path::Path;
+
+// metadata fields maintained by drogue-cloud, never taken from a local manifest.
+const SERVER_MANAGED_METADATA: [&str; 6] = [
+    "creationTimestamp",
+    "deletionTimestamp",
+    "generation",
+    "resourceVersion",
+    "uid",
+    "finalizers",
+];
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    App,
+    Device(String),
+}
+
+struct Resource {
+    kind: Kind,
+    name: String,
+    data: Value,
+}
+
+impl Resource {
+    fn from_document(data: Value, source: &str) -> Result<Resource> {
+        let metadata = &data["metadata"];
+        let name = metadata["name"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Missing `metadata.name` in a document of {}", source))?
+            .to_string();
+
+        // devices are the only resources owned by an application.
+        let kind = match metadata["application"].as_str() {
+            Some(app) => Kind::Device(app.to_string()),
+            None => Kind::App,
+        };
+
+        // fail early on documents which are not valid apps or devices, and compare them in the
+        // form the server side objects are read back in.
+        let mut canonical = match kind {
+            Kind::App => from_value::<Application>(data.clone()).and_then(to_value),
+            Kind::Device(_) => from_value::<Device>(data.clone()).and_then(to_value),
+        }
+        .context(format!("Invalid resource {} in {}", name, source))?;
+
+        // the model adds defaults, e.g. an empty spec, which must not replace the server side fields.
+        if let (Some(canonical), Some(written)) = (canonical.as_object_mut(), data.as_object()) {
+            let defaults: Vec<String> = canonical
+                .keys()
+                .filter(|key| !written.contains_key(*key))
+                .cloned()
+                .collect();
+            for key in defaults {
+                canonical.remove(&key);
+            }
+        }
+
+        Ok(Resource {
+            kind,
+            name,
+            data: canonical,
+        })
+    }
+
+    fn display_name(&self) -> String {
+        match &self.kind {
+            Kind::App => format!("App {}", self.name),
+            Kind::Device(app) => format!("Device {}/{}", app, self.name),
+        }
+    }
+
+    // Retrieve the server side object, `None` if it does not exist yet.
+    fn fetch(&self, client: &RegistryClient) -> Result<Option<Value>> {
+        let res = match &self.kind {
+            Kind::App => client.get_app(&self.name).map(to_value),
+            Kind::Device(app) => client.get_device(app, &self.name).map(to_value),
+        };
+
+        match res {
+            Ok(current) => Ok(Some(current?)),
+            Err(ClientError::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn create(&self, client: &RegistryClient) -> Result<()> {
+        match &self.kind {
+            Kind::App => client.create_app(&from_value(self.data.clone())?),
+            Kind::Device(app) => client.create_device(app, &from_value(self.data.clone())?),
+        }?;
+        Ok(())
+    }
+
+    fn update(&self, client: &RegistryClient, data: Value) -> Result<()> {
+        match &self.kind {
+            Kind::App => client.update_app(&self.name, &from_value(data)?),
+            Kind::Device(app) => client.update_device(app, &self.name, &from_value(data)?),
+        }?;
+        Ok(())
+    }
+}
+
+/// Create or update every app and device found in the given files or directories.
+pub fn apply(config: &Context, paths: Vec<&str>) -> Result<()> {
+    let client = RegistryClient::new(config);
+
+    for resource in load_resources(paths)? {
+        let name = resource.display_name();
+
+        match resource.fetch(&client)? {
+            Some(current) => {
+                let desired = desired_state(&current, &resource.data);
+
+                if desired == current {
+                    println!("{} unchanged.", name);
+                } else {
+                    resource.update(&client, desired)?;
+                    println!("{} updated.", name);
+                }
+            }
+            None => {
+                resource.create(&client)?;
+                println!("{} created.", name);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print the differences between the server side resources and the files.
+/// Returns true if any resource would be changed by an apply.
+pub fn diff(config: &Context, paths: Vec<&str>) -> Result<bool> {
+    let client = RegistryClient::new(config);
+    let mut changed = false;
+
+    for resource in load_resources(paths)? {
+        let name = resource.display_name();
+
+        let (current, desired) = match resource.fetch(&client)? {
+            Some(current) => {
+                let desired = desired_state(&current, &resource.data);
+                (strip_server_fields(current), strip_server_fields(desired))
+            }
+            None => (Value::Null, strip_server_fields(resource.data)),
+        };
+
+        if current != desired {
+            changed = true;
+            print_diff(&name, &current, &desired)?;
+        }
+    }
+
+    Ok(changed)
+}
+
+// Overlay the local manifest onto the server side object, keeping the fields managed by the server.
+fn desired_state(current: &Value, local: &Value) -> Value {
+    let mut desired = current.clone();
+
+    if let (Some(desired), Some(local)) = (desired.as_object_mut(), local.as_object()) {
+        for (key, value) in local {
+            match key.as_str() {
+                "status" => {}
+                "metadata" => {
+                    let metadata = desired
+                        .entry("metadata")
+                        .or_insert_with(|| Value::Object(Default::default()));
+                    if let (Some(metadata), Some(local)) =
+                        (metadata.as_object_mut(), value.as_object())
+                    {
+                        for (k, v) in local {
+                            if !SERVER_MANAGED_METADATA.contains(&k.as_str()) {
+                                metadata.insert(k.clone(), v.clone());
+                            }
+                        }
+                    }
+                }
+                _ => {
+                    desired.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+
+    desired
+}
+
+fn strip_server_fields(mut data: Value) -> Value {
+    if let Some(metadata) = data["metadata"].as_object_mut() {
+        for field in SERVER_MANAGED_METADATA.iter() {
+            metadata.remove(*field);
+        }
+    }
+    data
+}
+
+fn print_diff(name: &str, current: &Value, desired: &Value) -> Result<()> {
+    let to_yaml = |v: &Value| -> Result<String> {
+        match v {
+            Value::Null => Ok(String::new()),
+            v => Ok(serde_yaml::to_string(v)?
+                .trim_start_matches("---\n")
+                .to_string()),
+        }
+    };
+    let (current, desired) = (to_yaml(current)?, to_yaml(desired)?);
+
+    let diff = TextDiff::from_lines(&current, &desired);
+    let unified = diff
+        .unified_diff()
+        .header(&format!("{} (server)", name), &format!("{} (local)", name))
+        .to_string();
+
+    for line in unified.lines() {
+        if line.starts_with("---") || line.starts_with("+++") {
+            println!("{}", line.bold());
+        } else if line.starts_with("@@") {
+            println!("{}", line.cyan());
+        } else if line.starts_with('-') {
+            println!("{}", line.red());
+        } else if line.starts_with('+') {
+            println!("{}", line.green());
+        } else {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+// Read all documents from the given paths. Apps are sorted first so their devices can be created.
+fn load_resources(paths: Vec<&str>) -> Result<Vec<Resource>> {
+    let mut resources = Vec::new();
+
+    for path in paths {
+        let path = Path::new(path);
+        if path.is_dir() {
+            let mut files: Vec<_> = fs::read_dir(path)
+                .context(format!("Cannot read directory {}", path.display()))?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|p| {
+                    p.is_file()
+                        && matches!(
+                            p.extension().and_then(|e| e.to_str()),
+                            Some("yaml") | Some("yml") | Some("json")
+                        )
+                })
+                .collect();
+            files.sort();
+
+            for file in files {
+                resources.extend(read_documents(&file)?);
+            }
+        } else {
+            resources.extend(read_documents(path)?);
+        }
+    }
+
+    resources.sort_by(|a, b| a.kind.cmp(&b.kind));
+    Ok(resources)
+}
+
+// JSON being valid YAML, both formats are handled by the YAML parser.
+fn read_documents(path: &Path) -> Result<Vec<Resource>> {
+    let source = path.display().to_string();
+    let contents =
+        fs::read_to_string(path).context(format!("Something went wrong reading {}", source))?;
+
+    let mut resources = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(&contents) {
+        let data = Value::deserialize(document).context(format!("Invalid YAML in {}", source))?;
+        // skip empty documents, e.g. a trailing `---`
+        if !data.is_null() {
+            resources.push(Resource::from_document(data, &source)?);
+        }
+    }
+
+    Ok(resources)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn device() -> Value {
+        json!({
+            "metadata": {
+                "name": "foo",
+                "application": "app",
+                "resourceVersion": "1",
+                "labels": {"a": "1"},
+            },
+            "spec": {
+                "credentials": {"credentials": [{"pass": "secret"}]},
+                "gatewaySelector": {"matchNames": ["bar"]},
+            },
+        })
+    }
+
+    #[test]
+    fn metadata_only_manifest_keeps_spec() -> Result<()> {
+        let manifest = json!({
+            "metadata": {"name": "foo", "application": "app", "labels": {"a": "2"}},
+        });
+        let resource = Resource::from_document(manifest, "test")?;
+        assert!(resource.data.get("spec").is_none());
+
+        let desired = desired_state(&device(), &resource.data);
+        assert_eq!(desired["spec"], device()["spec"]);
+        assert_eq!(desired["metadata"]["labels"], json!({"a": "2"}));
+        assert_eq!(desired["metadata"]["resourceVersion"], json!("1"));
+        Ok(())
+    }
+
+    #[test]
+    fn canonical_manifest_is_unchanged() -> Result<()> {
+        let manifest = json!({
+            "metadata": {"name": "foo", "application": "app", "labels": {"a": "1"}},
+            "spec": {
+                "credentials": {"credentials": [{"pass": {"plain": "secret"}}]},
+                "gatewaySelector": {"matchNames": ["bar"]},
+            },
+        });
+        let resource = Resource::from_document(manifest, "test")?;
+
+        assert_eq!(desired_state(&device(), &resource.data), device());
+        Ok(())
+    }
+
+    #[test]
+    fn empty_credentials_are_canonical() -> Result<()> {
+        let manifest = json!({
+            "metadata": {"name": "foo", "application": "app"},
+            "spec": {"credentials": {}},
+        });
+        let resource = Resource::from_document(manifest, "test")?;
+
+        assert_eq!(
+            resource.data["spec"],
+            json!({"credentials": {"credentials": []}})
+        );
+        Ok(())
+    }
+}
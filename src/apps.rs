@@ -1,140 +1,83 @@
+use crate::client::RegistryClient;
 use crate::config::Context;
-use crate::{trust, util, AppId, Verbs};
+use crate::models::Application;
+use crate::outputs::{self, OutputFormat};
+use crate::{trust, util, AppId};
 use anyhow::{anyhow, Context as AnyhowContext, Result};
-use json_value_merge::Merge;
-use oauth2::TokenResponse;
-use reqwest::blocking::{Client, Response};
-use reqwest::{StatusCode, Url};
-use serde_json::{from_str, json, Value};
-use std::process::exit;
+use serde_json::from_value;
+use std::time::Duration;
 use tabular::{Row, Table};
 
-fn craft_url(base: &Url, app_id: Option<&str>) -> String {
-    let app = match app_id {
-        Some(app) => format!("/{}", app),
-        None => String::new(),
-    };
-    format!("{}{}/apps{}", base, util::API_PATH, app)
-}
-
 pub fn create(
     config: &Context,
     app: AppId,
     data: serde_json::Value,
     file: Option<&str>,
 ) -> Result<()> {
-    let client = Client::new();
-    let url = craft_url(&config.registry_url, None);
     let body = match file {
-        Some(f) => util::get_data_from_file(f)?,
-        None => {
-            json!({
-            "metadata": {
-                "name": app,
-            },
-            "spec": data,
-            })
-        }
+        Some(f) => from_value(util::get_data_from_file(f)?).context("Invalid app in file")?,
+        None => Application {
+            spec: from_value(data).context("Invalid app spec")?,
+            ..Application::new(app.clone())
+        },
     };
 
-    client
-        .post(&url)
-        .header(reqwest::header::CONTENT_TYPE, "application/json")
-        .body(body.to_string())
-        .bearer_auth(&config.token.access_token().secret())
-        .send()
-        .context("Can't create app.")
-        .map(|res| util::print_result(res, format!("App {}", app), Verbs::create))
+    RegistryClient::new(config).create_app(&body)?;
+    println!("App {} created.", app);
+    Ok(())
 }
 
-pub fn read(config: &Context, app: AppId) -> Result<()> {
-    get(config, &app).map(|res| util::print_result(res, app.to_string(), Verbs::get))
+pub fn read(config: &Context, app: AppId, output: OutputFormat) -> Result<()> {
+    let app = RegistryClient::new(config).get_app(&app)?;
+    outputs::show_object(app, output, pretty_list)
 }
 
 pub fn delete(config: &Context, app: AppId) -> Result<()> {
-    let client = Client::new();
-    let url = craft_url(&config.registry_url, Some(&app));
-
-    client
-        .delete(&url)
-        .bearer_auth(&config.token.access_token().secret())
-        .send()
-        .context("Can't get app.")
-        .map(|res| util::print_result(res, format!("App {}", &app), Verbs::delete))
+    RegistryClient::new(config).delete_app(&app)?;
+    println!("App {} deleted.", app);
+    Ok(())
 }
 
 pub fn edit(config: &Context, app: AppId, file: Option<&str>) -> Result<()> {
-    match file {
+    let client = RegistryClient::new(config);
+    let updated = match file {
         Some(f) => {
-            let data = util::get_data_from_file(f)?;
-
-            put(&config, &app, data)
-                .map(|res| util::print_result(res, format!("App {}", &app), Verbs::edit))
+            let data: Application =
+                from_value(util::get_data_from_file(f)?).context("Invalid app in file")?;
+            client.update_app(&app, &data)?;
+            true
         }
-        None => {
-            //read app data
-            let res = get(config, &app);
-            match res {
-                Ok(r) => match r.status() {
-                    StatusCode::OK => {
-                        let body = r.text().unwrap_or_else(|_| "{}".to_string());
-                        let insert = util::editor(body)?;
+        None => util::edit_resource(
+            || client.get_app(&app),
+            |data| client.update_app(&app, data),
+        )?,
+    };
 
-                        put(config, &app, insert)
-                            .map(|p| util::print_result(p, format!("App {}", &app), Verbs::edit))
-                    }
-                    e => {
-                        log::error!("Error : could not retrieve app: {}", e);
-                        util::exit_with_code(e)
-                    }
-                },
-                Err(e) => {
-                    log::error!("Error : could not retrieve app: {}", e);
-                    exit(2);
-                }
-            }
-        }
+    if updated {
+        println!("App {} updated.", app);
     }
+    Ok(())
 }
 
-pub fn list(config: &Context, labels: Option<String>) -> Result<()> {
-    let client = Client::new();
-    let url = craft_url(&config.registry_url, None);
-
-    let mut req = client
-        .get(&url)
-        .bearer_auth(&config.token.access_token().secret());
-
-    if let Some(labels) = labels {
-        req = req.query(&[("labels", labels)]);
-    }
-
-    let res = req.send().context("Can't list apps");
-
-    if let Ok(r) = res {
-        match r.status() {
-            StatusCode::OK => {
-                pretty_list(r.text()?)?;
-                Ok(())
-            }
-            e => {
-                log::error!("List operation failed with {}", r.status());
-                util::exit_with_code(e)
-            }
-        }
-    } else {
-        Err(anyhow!("Error while requesting app list."))
-    }
+pub fn list(config: &Context, labels: Option<String>, output: OutputFormat) -> Result<()> {
+    let apps = RegistryClient::new(config).list_apps(labels.as_deref())?;
+    outputs::show_list(apps, output, pretty_list)
 }
 
-fn get(config: &Context, app: &str) -> Result<Response> {
-    let client = Client::new();
-    let url = craft_url(&config.registry_url, Some(app));
-    client
-        .get(&url)
-        .bearer_auth(&config.token.access_token().secret())
-        .send()
-        .context("Can't retrieve app data.")
+pub fn watch(
+    config: &Context,
+    labels: Option<String>,
+    save_token: &dyn Fn(&Context) -> Result<()>,
+    output: OutputFormat,
+    interval: Duration,
+) -> Result<()> {
+    util::watch(
+        config,
+        |context| RegistryClient::new(context).list_apps(labels.as_deref()),
+        save_token,
+        output,
+        interval,
+    )
 }
 
 pub fn add_trust_anchor(
@@ -143,90 +86,58 @@ pub fn add_trust_anchor(
     keyout: Option<&str>,
     days: Option<&str>,
 ) -> Result<()> {
-    let res = get(config, &app);
-    match res {
-        Ok(r) => match r.status() {
-            StatusCode::OK => {
-                let app_obj = r.text().unwrap_or_else(|_| "{}".to_string());
-                let mut app_obj: Value = serde_json::from_str(&app_obj)?;
-                app_obj.merge(json!({
-                    "spec": trust::create_trust_anchor(app, keyout, days)?
-                }));
-
-                put(config, app, app_obj)
-                    .map(|p| util::print_result(p, format!("App {}", &app), Verbs::edit))
-            }
-            e => {
-                log::error!("Error : could not retrieve app: {}", e);
-                util::exit_with_code(e)
-            }
-        },
-        Err(e) => {
-            log::error!("Error : could not retrieve app: {}", e);
-            exit(2);
-        }
-    }
+    let client = RegistryClient::new(config);
+    let mut app_obj = client.get_app(app)?;
+    let anchor = trust::create_trust_anchor(app, keyout, days)?;
+
+    app_obj
+        .spec
+        .trust_anchors
+        .get_or_insert_with(Default::default)
+        .anchors
+        .push(anchor);
+
+    client.update_app(app, &app_obj)?;
+    println!("App {} updated.", app);
+    Ok(())
 }
 
 pub fn get_trust_anchor(config: &Context, app: &str) -> Result<String> {
-    let res = get(config, &app);
-    match res {
-        Ok(r) => match r.status() {
-            StatusCode::OK => {
-                let app_obj = r.text().unwrap_or_else(|_| "{}".to_string());
-                let app_obj_json: Value = serde_json::from_str(&app_obj)?;
-                let cert =
-                    app_obj_json["spec"]["trustAnchors"]["anchors"][0]["certificate"].clone();
-
-                if cert == Value::Null {
-                    log::error!("No trust anchor found in this application.");
-                    exit(1);
-                }
-
-                Ok(cert.to_string().replace("\"", ""))
-            }
-            e => {
-                log::error!("Error : could not retrieve app: {}", e);
-                util::exit_with_code(e)
-            }
-        },
-        Err(e) => {
-            log::error!("Error : could not retrieve app: {}", e);
-            exit(2);
-        }
-    }
+    RegistryClient::new(config)
+        .get_app(app)?
+        .spec
+        .trust_anchors
+        .and_then(|t| t.anchors.into_iter().next())
+        .map(|anchor| anchor.certificate)
+        .ok_or_else(|| anyhow!("No trust anchor found in this application."))
 }
 
-fn put(config: &Context, app: &str, data: serde_json::Value) -> Result<Response> {
-    let client = Client::new();
-    let url = craft_url(&config.registry_url, Some(app));
-
-    client
-        .put(&url)
-        .header(reqwest::header::CONTENT_TYPE, "application/json")
-        .bearer_auth(&config.token.access_token().secret())
-        .body(data.to_string())
-        .send()
-        .context("Can't update app data.")
-}
-
-// todo drogue-client and the types would be useful for this
-fn pretty_list(data: String) -> Result<()> {
-    let apps_array: Vec<Value> = from_str(data.as_str())?;
-
-    let mut table = Table::new("{:<} {:<}");
-    table.add_row(Row::new().with_cell("NAME").with_cell("AGE"));
+fn pretty_list(apps: &[Application], wide: bool) -> Result<()> {
+    let mut table = if wide {
+        let mut table = Table::new("{:<} {:<} {:<} {:<}");
+        table.add_row(
+            Row::new()
+                .with_cell("NAME")
+                .with_cell("AGE")
+                .with_cell("LABELS")
+                .with_cell("STATUS"),
+        );
+        table
+    } else {
+        let mut table = Table::new("{:<} {:<}");
+        table.add_row(Row::new().with_cell("NAME").with_cell("AGE"));
+        table
+    };
 
-    for app in apps_array {
-        let name = app["metadata"]["name"].as_str();
-        let creation = app["metadata"]["creationTimestamp"].as_str();
-        if let Some(name) = name {
-            table.add_row(
-                Row::new()
-                    .with_cell(name)
-                    .with_cell(util::age(creation.unwrap())?),
-            );
+    for app in apps {
+        let mut row = Row::new()
+            .with_cell(&app.metadata.name)
+            .with_cell(util::age(&app.metadata));
+        if wide {
+            row.add_cell(outputs::labels_column(&app.metadata));
+            row.add_cell(outputs::conditions_column(&app.status));
         }
+        table.add_row(row);
     }
 
     print!("{}", table);
@@ -1,19 +1,28 @@
+use crate::devices::{self, CredentialSelector};
+use crate::models::{Credential, PreSharedKey};
+use crate::outputs::OutputFormat;
 use crate::{util, AppId};
 
-use crate::config::Context;
-use anyhow::{anyhow, Result};
-use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use crate::config::{AccessToken, ClientCredentials, Context, OAuthClient};
+use crate::openid::{LoginMethod, RedirectAddress};
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use clap::{App, AppSettings, Arg, ArgGroup, ArgMatches, SubCommand};
 use std::convert::AsRef;
+use std::fs;
+use std::str::FromStr;
 use strum_macros::{AsRefStr, EnumString};
 
 #[derive(AsRefStr, EnumString)]
 #[allow(non_camel_case_types)]
 pub enum Verbs {
+    apply,
     create,
     delete,
+    diff,
     edit,
     get,
     set,
+    unset,
 }
 
 #[derive(AsRefStr, EnumString)]
@@ -21,6 +30,13 @@ pub enum Verbs {
 pub enum Set_targets {
     gateway,
     password,
+    psk,
+}
+
+#[derive(AsRefStr, EnumString)]
+#[allow(non_camel_case_types)]
+pub enum Unset_targets {
+    gateway,
 }
 
 #[derive(AsRefStr, EnumString)]
@@ -36,6 +52,8 @@ pub enum Resources {
     devices,
     app,
     apps,
+    credentials,
+    gateways,
 }
 
 #[derive(AsRefStr, EnumString)]
@@ -57,12 +75,55 @@ pub enum Parameters {
     ca_key,
     out,
     days,
+    output,
+    watch,
+    interval,
+    #[strum(serialize = "device-code")]
+    device_code,
+    #[strum(serialize = "client-id")]
+    client_id,
+    #[strum(serialize = "client-secret")]
+    client_secret,
+    #[strum(serialize = "client-secret-file")]
+    client_secret_file,
+    #[strum(serialize = "access-token")]
+    access_token,
+    description,
+    prefix,
+    remove,
+    #[strum(serialize = "oidc-client-id")]
+    oidc_client_id,
+    #[strum(serialize = "oidc-client-secret")]
+    oidc_client_secret,
+    scope,
+    #[strum(serialize = "redirect-host")]
+    redirect_host,
+    #[strum(serialize = "redirect-port")]
+    redirect_port,
+    #[strum(serialize = "show-secrets")]
+    show_secrets,
+    password,
+    unique,
+    psk,
+    #[strum(serialize = "cert-alias")]
+    cert_alias,
+    index,
+    #[strum(serialize = "type")]
+    credential_type,
+    hash,
+    generate,
+    length,
+    hex,
+    add,
+    replace,
+    tree,
 }
 
 #[derive(AsRefStr, EnumString)]
 #[allow(non_camel_case_types)]
 pub enum Other_commands {
     login,
+    logout,
     token,
     version,
     whoami,
@@ -82,6 +143,8 @@ pub enum Context_subcommands {
     #[strum(serialize = "set-default-app")]
     set_default_app,
     rename,
+    encrypt,
+    decrypt,
 }
 
 #[derive(AsRefStr, EnumString)]
@@ -91,6 +154,14 @@ pub enum Trust_subcommands {
     add,
 }
 
+#[derive(AsRefStr, EnumString)]
+#[allow(non_camel_case_types)]
+pub enum Token_subcommands {
+    create,
+    list,
+    delete,
+}
+
 #[derive(AsRefStr, EnumString)]
 #[allow(non_camel_case_types)]
 pub enum Other_flags {
@@ -106,9 +177,82 @@ pub fn parse_arguments() -> ArgMatches<'static> {
     let set_arg = Arg::with_name(Verbs::set.as_ref())
         .required(true)
         .multiple(true)
-        .number_of_values(2)
-        .value_names(&["device","value"])
-        .help("For gateway value is the device id of the gateway, for setting a password credential, value is the password");
+        .min_values(2)
+        .value_names(&["device", "gateway"])
+        .help("The device id, followed by the device ids of the gateways");
+
+    let gateway_add = Arg::with_name(Parameters::add.as_ref())
+        .long(Parameters::add.as_ref())
+        .help("Add the gateways to the gateways of the device. This is the default.");
+
+    let gateway_remove = Arg::with_name(Parameters::remove.as_ref())
+        .long(Parameters::remove.as_ref())
+        .help("Remove the gateways from the gateways of the device.");
+
+    let gateway_replace = Arg::with_name(Parameters::replace.as_ref())
+        .long(Parameters::replace.as_ref())
+        .help("Replace the gateways of the device.");
+
+    let gateway_update = ArgGroup::with_name("update").args(&[
+        Parameters::add.as_ref(),
+        Parameters::remove.as_ref(),
+        Parameters::replace.as_ref(),
+    ]);
+
+    // the password is optional when it is generated.
+    let set_password_arg = Arg::with_name(Verbs::set.as_ref())
+        .required(true)
+        .multiple(true)
+        .min_values(1)
+        .max_values(2)
+        .value_names(&["device", "password"])
+        .help("The device id, and the password unless --generate is used");
+
+    let password_hash = Arg::with_name(Parameters::hash.as_ref())
+        .long(Parameters::hash.as_ref())
+        .takes_value(true)
+        .possible_values(&["bcrypt", "sha512"])
+        .help("Hash the password before sending it, only the hash is stored in the device.");
+
+    let password_generate = Arg::with_name(Parameters::generate.as_ref())
+        .long(Parameters::generate.as_ref())
+        .short("g")
+        .help("Generate a random password, it is printed once.");
+
+    let password_out = Arg::with_name(Parameters::out.as_ref())
+        .long(Parameters::out.as_ref())
+        .takes_value(true)
+        .value_name("FILE")
+        .requires(Parameters::generate.as_ref())
+        .help("Write the generated password to a file instead of printing it.");
+
+    let set_psk_arg = set_password_arg
+        .clone()
+        .value_names(&["device", "key"])
+        .help("The device id, and the pre-shared key unless --generate is used. The key is base64 encoded, or hex encoded with --hex");
+
+    let psk_generate = Arg::with_name(Parameters::generate.as_ref())
+        .long(Parameters::generate.as_ref())
+        .short("g")
+        .help("Generate a random key.");
+
+    let psk_length = Arg::with_name(Parameters::length.as_ref())
+        .long(Parameters::length.as_ref())
+        .takes_value(true)
+        .value_name("BYTES")
+        .requires(Parameters::generate.as_ref())
+        .validator(|n| match n.parse::<usize>() {
+            Ok(n) if (16..=64).contains(&n) => Ok(()),
+            _ => Err(String::from(
+                "The key length must be between 16 and 64 bytes",
+            )),
+        })
+        .help("The length of the generated key, in bytes. [default: 32]");
+
+    let psk_hex = Arg::with_name(Parameters::hex.as_ref())
+        .long(Parameters::hex.as_ref())
+        .conflicts_with(Parameters::generate.as_ref())
+        .help("The key is hex encoded.");
 
     let url_arg = Arg::with_name(Parameters::url.as_ref())
         .required(true)
@@ -148,6 +292,19 @@ pub fn parse_arguments() -> ArgMatches<'static> {
             Note: unlike the --spec argument which cover only the spec section of the resource, \
             the file provided with --file must contains the complete resource object, including metadata.");
 
+    let apply_file_arg = Arg::with_name(Parameters::filename.as_ref())
+        .short("f")
+        .long(Parameters::filename.as_ref())
+        .takes_value(true)
+        .required(true)
+        .multiple(true)
+        .number_of_values(1)
+        .value_name("FILE")
+        .help("File or directory containing the resources to apply. Can be repeated.")
+        .long_help("File or directory containing the resources to apply. Can be repeated. \
+            Files may contain several YAML documents, directories are scanned for .yaml, .yml and .json files. \
+            A document with `metadata.application` set is a device, otherwise it is an app.");
+
     let token_arg = Arg::with_name(Other_commands::token.as_ref())
         .short("t")
         .takes_value(true)
@@ -169,6 +326,22 @@ pub fn parse_arguments() -> ArgMatches<'static> {
         .global(true)
         .help("Enable verbose output. Multiple occurences increase verbosity.");
 
+    let output_arg = Arg::with_name(Parameters::output.as_ref())
+        .long(Parameters::output.as_ref())
+        .short("o")
+        .takes_value(true)
+        .value_name("FORMAT")
+        .validator(|f| {
+            OutputFormat::from_str(&f)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+        .help("Output format of the get commands. One of: json, yaml, wide, name, jsonpath=..., template=...")
+        .long_help("Output format of the get commands. One of: json, yaml, wide, name, jsonpath=<expression>, template=<template>. \
+            jsonpath and template are evaluated for each resource of a list, e.g. -o jsonpath='$.metadata.name' \
+            or -o template='{metadata.name} {metadata.creationTimestamp}'. \
+            With json, errors are also reported as a JSON object on stderr.");
+
     let context_arg = Arg::with_name(Parameters::context.as_ref())
         .long(Parameters::context.as_ref())
         .short("c")
@@ -182,11 +355,198 @@ pub fn parse_arguments() -> ArgMatches<'static> {
         .required(true)
         .help("The id of the context");
 
+    let logout_context_arg = Arg::with_name(Parameters::context_name.as_ref())
+        .help("The context to log out from. Defaults to the active context.");
+
+    let logout_remove = Arg::with_name(Parameters::remove.as_ref())
+        .long(Parameters::remove.as_ref())
+        .help("Also remove the context from the configuration file.");
+
     let login_keep_current = Arg::with_name(Parameters::keep_current.as_ref())
         .short("k")
         .help("Do not activate the new context.");
 
-    let labels = Arg::with_name(&Parameters::labels.as_ref())
+    let login_device_code = Arg::with_name(Parameters::device_code.as_ref())
+        .long(Parameters::device_code.as_ref())
+        .conflicts_with(Other_commands::token.as_ref())
+        .help("Log in with a code entered in a browser on another device, when no browser can be opened from here.");
+
+    let login_client_id = Arg::with_name(Parameters::client_id.as_ref())
+        .long(Parameters::client_id.as_ref())
+        .takes_value(true)
+        .env("DRG_CLIENT_ID")
+        .conflicts_with_all(&[
+            Other_commands::token.as_ref(),
+            Parameters::device_code.as_ref(),
+            Parameters::access_token.as_ref(),
+        ])
+        .help("Log in as a service account with the client credentials grant. Can be set with DRG_CLIENT_ID environment variable.");
+
+    let login_client_secret = Arg::with_name(Parameters::client_secret.as_ref())
+        .long(Parameters::client_secret.as_ref())
+        .takes_value(true)
+        .env("DRG_CLIENT_SECRET")
+        .hide_env_values(true)
+        .requires(Parameters::client_id.as_ref())
+        .conflicts_with(Parameters::client_secret_file.as_ref())
+        .help("The secret of the service account. Can be set with DRG_CLIENT_SECRET environment variable.");
+
+    let login_client_secret_file = Arg::with_name(Parameters::client_secret_file.as_ref())
+        .long(Parameters::client_secret_file.as_ref())
+        .takes_value(true)
+        .value_name("FILE")
+        .requires(Parameters::client_id.as_ref())
+        .help("Read the secret of the service account from a file.");
+
+    let login_access_token = Arg::with_name(Parameters::access_token.as_ref())
+        .long(Parameters::access_token.as_ref())
+        .takes_value(true)
+        .value_name("USERNAME:TOKEN")
+        .env("DRG_ACCESS_TOKEN")
+        .hide_env_values(true)
+        .conflicts_with_all(&[
+            Other_commands::token.as_ref(),
+            Parameters::device_code.as_ref(),
+        ])
+        .validator(|t| match t.split_once(':') {
+            Some((user, token)) if !user.is_empty() && !token.is_empty() => Ok(()),
+            _ => Err("The access token must be given as <username>:<token>".to_string()),
+        })
+        .help("Authenticate with a drogue-cloud access token instead of openid-connect. Can be set with DRG_ACCESS_TOKEN environment variable.");
+
+    let login_oidc_client_id = Arg::with_name(Parameters::oidc_client_id.as_ref())
+        .long(Parameters::oidc_client_id.as_ref())
+        .takes_value(true)
+        .env("DRG_OIDC_CLIENT_ID")
+        .conflicts_with_all(&[
+            Parameters::client_id.as_ref(),
+            Parameters::access_token.as_ref(),
+        ])
+        .help("The openid-connect client to log in with, instead of the drogue client. Can be set with DRG_OIDC_CLIENT_ID environment variable.");
+
+    let login_oidc_client_secret = Arg::with_name(Parameters::oidc_client_secret.as_ref())
+        .long(Parameters::oidc_client_secret.as_ref())
+        .takes_value(true)
+        .env("DRG_OIDC_CLIENT_SECRET")
+        .hide_env_values(true)
+        .requires(Parameters::oidc_client_id.as_ref())
+        .help("The secret of a confidential openid-connect client. Can be set with DRG_OIDC_CLIENT_SECRET environment variable.");
+
+    let login_scope = Arg::with_name(Parameters::scope.as_ref())
+        .long(Parameters::scope.as_ref())
+        .takes_value(true)
+        .multiple(true)
+        .use_delimiter(true)
+        .number_of_values(1)
+        .conflicts_with_all(&[
+            Parameters::client_id.as_ref(),
+            Parameters::access_token.as_ref(),
+        ])
+        .help("An additional scope to request, offline_access is always requested.");
+
+    let login_redirect_host = Arg::with_name(Parameters::redirect_host.as_ref())
+        .long(Parameters::redirect_host.as_ref())
+        .takes_value(true)
+        .conflicts_with_all(&[
+            Other_commands::token.as_ref(),
+            Parameters::device_code.as_ref(),
+            Parameters::client_id.as_ref(),
+            Parameters::access_token.as_ref(),
+        ])
+        .help("The host of the redirect URI, drg listens on the address it resolves to. Defaults to localhost.");
+
+    let login_redirect_port = Arg::with_name(Parameters::redirect_port.as_ref())
+        .long(Parameters::redirect_port.as_ref())
+        .takes_value(true)
+        .conflicts_with_all(&[
+            Other_commands::token.as_ref(),
+            Parameters::device_code.as_ref(),
+            Parameters::client_id.as_ref(),
+            Parameters::access_token.as_ref(),
+        ])
+        .validator(|p| {
+            p.parse::<u16>()
+                .map(|_| ())
+                .map_err(|_| "The redirect port must be a port number".to_string())
+        })
+        .help("The port of the redirect URI, a random port is used by default.");
+
+    let token_description = Arg::with_name(Parameters::description.as_ref())
+        .long(Parameters::description.as_ref())
+        .short("d")
+        .takes_value(true)
+        .help("A description of what the access token is used for.");
+
+    let token_prefix = Arg::with_name(Parameters::prefix.as_ref())
+        .required(true)
+        .help("The prefix of the access token.");
+
+    let device_resource_id_arg = Arg::with_name(Parameters::id.as_ref())
+        .required(true)
+        .value_name("DEVICE")
+        .help("The id of the device.");
+
+    let credential_password = Arg::with_name(Parameters::password.as_ref())
+        .long(Parameters::password.as_ref())
+        .takes_value(true)
+        .help("Add a password credential.");
+
+    let credential_username = set_password_username
+        .clone()
+        .requires(Parameters::password.as_ref());
+
+    let credential_unique = Arg::with_name(Parameters::unique.as_ref())
+        .long(Parameters::unique.as_ref())
+        .requires(Set_args::username.as_ref())
+        .help("The username must be unique in the app, and can be used to authenticate without the device id.");
+
+    let credential_psk = Arg::with_name(Parameters::psk.as_ref())
+        .long(Parameters::psk.as_ref())
+        .takes_value(true)
+        .value_name("KEY")
+        .help("Add a pre-shared key credential, base64 encoded unless --hex is given.");
+
+    let credential_cert_alias = Arg::with_name(Parameters::cert_alias.as_ref())
+        .long(Parameters::cert_alias.as_ref())
+        .takes_value(true)
+        .value_name("ALIAS")
+        .help("Add an alias matching the subject of a device certificate.");
+
+    let credential_kind = ArgGroup::with_name("credential")
+        .args(&[
+            Parameters::password.as_ref(),
+            Parameters::psk.as_ref(),
+            Parameters::cert_alias.as_ref(),
+        ])
+        .required(true);
+
+    let credential_index = Arg::with_name(Parameters::index.as_ref())
+        .long(Parameters::index.as_ref())
+        .takes_value(true)
+        .validator(|n| match n.parse::<usize>() {
+            Ok(_) => Ok(()),
+            Err(_) => Err(String::from("The value is not a positive integer")),
+        })
+        .help("Remove the credential at this index, as listed by drg get credentials.");
+
+    let credential_type = Arg::with_name(Parameters::credential_type.as_ref())
+        .long(Parameters::credential_type.as_ref())
+        .takes_value(true)
+        .possible_values(&["user", "pass", "psk", "cert"])
+        .help("Remove all the credentials of this type.");
+
+    let credential_selector = ArgGroup::with_name("selector")
+        .args(&[
+            Parameters::index.as_ref(),
+            Parameters::credential_type.as_ref(),
+        ])
+        .required(true);
+
+    let gateways_tree = Arg::with_name(Parameters::tree.as_ref())
+        .long(Parameters::tree.as_ref())
+        .help("Show the devices behind each gateway as a tree.");
+
+    let labels = Arg::with_name(Parameters::labels.as_ref())
         .required(false)
         .short("l")
         .long(Parameters::labels.as_ref())
@@ -194,34 +554,51 @@ pub fn parse_arguments() -> ArgMatches<'static> {
         .multiple(true)
         .help("A comma separated list of the label filters to filter the list with.");
 
-    let keyout = Arg::with_name(&Parameters::key_output.as_ref())
+    let watch = Arg::with_name(Parameters::watch.as_ref())
+        .short("w")
+        .long(Parameters::watch.as_ref())
+        .takes_value(false)
+        .help("Keep polling the list and print the resources added, modified or deleted.");
+
+    let watch_interval = Arg::with_name(Parameters::interval.as_ref())
+        .long(Parameters::interval.as_ref())
+        .takes_value(true)
+        .value_name("SECONDS")
+        .requires(Parameters::watch.as_ref())
+        .help("Polling interval of the watch mode, in seconds. [default: 5]")
+        .validator(|n| match n.parse::<u64>() {
+            Ok(n) if n > 0 => Ok(()),
+            _ => Err(String::from("The value is not a positive integer")),
+        });
+
+    let keyout = Arg::with_name(Parameters::key_output.as_ref())
         .takes_value(true)
         .required(false)
         .long(Parameters::key_output.as_ref())
         .help("Output file containing the private key. Later to be used to sign device certificates, or device authentication.");
 
-    let device_id_arg = Arg::with_name(&Resources::device.as_ref())
+    let device_id_arg = Arg::with_name(Resources::device.as_ref())
         .short("d")
         .required(true)
-        .long(&Resources::device.as_ref())
+        .long(Resources::device.as_ref())
         .takes_value(true)
         .help("Device id");
 
-    let ca_key = Arg::with_name(&Parameters::ca_key.as_ref())
-        .long(&Parameters::ca_key.as_ref())
+    let ca_key = Arg::with_name(Parameters::ca_key.as_ref())
+        .long(Parameters::ca_key.as_ref())
         .takes_value(true)
         .required(true)
         .help("Private key of the CA i.e application.");
 
-    let cert_out = Arg::with_name(&Parameters::out.as_ref())
-        .long(&Parameters::out.as_ref())
+    let cert_out = Arg::with_name(Parameters::out.as_ref())
+        .long(Parameters::out.as_ref())
         .short("o")
         .takes_value(true)
         .required(false)
         .help("Output device certificate to file.");
 
-    let device_name_subj = Arg::with_name(&Other_flags::cert.as_ref())
-        .long(&Other_flags::cert.as_ref())
+    let device_name_subj = Arg::with_name(Other_flags::cert.as_ref())
+        .long(Other_flags::cert.as_ref())
         .takes_value(false)
         .help("Creates device with the same name as the subject of device certificate.")
         .long_help(
@@ -231,8 +608,8 @@ pub fn parse_arguments() -> ArgMatches<'static> {
         );
 
     // Default value comes from trust::CERT_VALIDITY_DAYS
-    let cert_valid_days = Arg::with_name(&Parameters::days.as_ref())
-        .long(&Parameters::days.as_ref())
+    let cert_valid_days = Arg::with_name(Parameters::days.as_ref())
+        .long(Parameters::days.as_ref())
         .takes_value(true)
         .required(false)
         .help("Number of days the certificate should be valid for. [default: 365]")
@@ -269,8 +646,38 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                         .arg(&resource_id_arg)
                         .arg(&spec_arg)
                         .arg(&file_arg),
+                )
+                .subcommand(
+                    SubCommand::with_name(Resources::credentials.as_ref())
+                        .about("add a credential to a device.")
+                        .arg(&device_resource_id_arg)
+                        .arg(&app_id_arg)
+                        .arg(&credential_password)
+                        .arg(&credential_username)
+                        .arg(&credential_unique)
+                        .arg(password_hash.clone().requires(Parameters::password.as_ref()))
+                        .arg(&credential_psk)
+                        .arg(psk_hex.clone().requires(Parameters::psk.as_ref()))
+                        .arg(&credential_cert_alias)
+                        .group(credential_kind),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name(Verbs::apply.as_ref())
+                .about("Create or update apps and devices from files")
+                .setting(AppSettings::ArgRequiredElseHelp)
+                .arg(&apply_file_arg),
+        )
+        .subcommand(
+            SubCommand::with_name(Verbs::diff.as_ref())
+                .about("Show the changes an apply would make to the resources")
+                .long_about(
+                    "Show the changes an apply would make to the resources. \
+                    Exits with code 8 when differences are found, and 0 when there are none.",
+                )
+                .setting(AppSettings::ArgRequiredElseHelp)
+                .arg(&apply_file_arg),
+        )
         .subcommand(
             SubCommand::with_name(Verbs::delete.as_ref())
                 .visible_alias("remove")
@@ -286,11 +693,21 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                     SubCommand::with_name(Resources::app.as_ref())
                         .about("delete an app.")
                         .arg(&resource_id_arg),
+                )
+                .subcommand(
+                    SubCommand::with_name(Resources::credentials.as_ref())
+                        .about("remove credentials from a device.")
+                        .arg(&device_resource_id_arg)
+                        .arg(&app_id_arg)
+                        .arg(&credential_index)
+                        .arg(&credential_type)
+                        .group(credential_selector),
                 ),
         )
         .subcommand(
             SubCommand::with_name(Verbs::get.as_ref())
                 .about("Display one or many resources from the drogue-cloud registry")
+                .arg(output_arg.clone().global(true))
                 .setting(AppSettings::ArgRequiredElseHelp)
                 .subcommand(
                     SubCommand::with_name(Resources::device.as_ref())
@@ -309,14 +726,30 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                         .about("List all apps.")
                         .arg(&labels)
                         .about("List all apps the user have access to.")
-                        .arg(resource_id_arg.clone().required(false)),
+                        .arg(resource_id_arg.clone().required(false))
+                        .arg(&watch)
+                        .arg(&watch_interval),
                 )
                 .subcommand(
                     SubCommand::with_name(Resources::devices.as_ref())
                         .arg(&app_id_arg)
                         .arg(&labels)
                         .about("List all devices for an app.")
-                        .arg(resource_id_arg.clone().required(false)),
+                        .arg(resource_id_arg.clone().required(false))
+                        .arg(&watch)
+                        .arg(&watch_interval),
+                )
+                .subcommand(
+                    SubCommand::with_name(Resources::credentials.as_ref())
+                        .about("List the credentials of a device, the secrets are masked.")
+                        .arg(&device_resource_id_arg)
+                        .arg(&app_id_arg),
+                )
+                .subcommand(
+                    SubCommand::with_name(Resources::gateways.as_ref())
+                        .about("List the gateways of an app, and the devices behind them.")
+                        .arg(&app_id_arg)
+                        .arg(&gateways_tree),
                 ),
         )
         .subcommand(
@@ -344,16 +777,43 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                 .setting(AppSettings::ArgRequiredElseHelp)
                 .subcommand(
                     SubCommand::with_name(Set_targets::gateway.as_ref())
-                        .about("Set a gateway for a device.")
+                        .about("Set the gateways of a device.")
                         .arg(&set_arg)
-                        .arg(&app_id_arg),
+                        .arg(&app_id_arg)
+                        .arg(&gateway_add)
+                        .arg(&gateway_remove)
+                        .arg(&gateway_replace)
+                        .group(gateway_update),
                 )
                 .subcommand(
                     SubCommand::with_name(Set_targets::password.as_ref())
                         .about("Set a password credentials for a device")
-                        .arg(&set_arg)
+                        .arg(&set_password_arg)
                         .arg(&app_id_arg)
-                        .arg(&set_password_username),
+                        .arg(&set_password_username)
+                        .arg(&password_hash)
+                        .arg(&password_generate)
+                        .arg(&password_out),
+                )
+                .subcommand(
+                    SubCommand::with_name(Set_targets::psk.as_ref())
+                        .about("Set a pre-shared key credential for a device, and print the provisioning details")
+                        .arg(&set_psk_arg)
+                        .arg(&app_id_arg)
+                        .arg(&psk_generate)
+                        .arg(&psk_length)
+                        .arg(&psk_hex),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Verbs::unset.as_ref())
+                .about("Remove the configuration of apps or devices resources")
+                .setting(AppSettings::ArgRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name(Unset_targets::gateway.as_ref())
+                        .about("Remove all the gateways of a device.")
+                        .arg(&device_resource_id_arg)
+                        .arg(&app_id_arg),
                 ),
         )
         .subcommand(
@@ -365,7 +825,23 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                 .arg(&token_arg)
                 .about("Log into a drogue cloud installation.")
                 .arg(&url_arg)
-                .arg(&login_keep_current),
+                .arg(&login_keep_current)
+                .arg(&login_device_code)
+                .arg(&login_client_id)
+                .arg(&login_client_secret)
+                .arg(&login_client_secret_file)
+                .arg(&login_access_token)
+                .arg(&login_oidc_client_id)
+                .arg(&login_oidc_client_secret)
+                .arg(&login_scope)
+                .arg(&login_redirect_host)
+                .arg(&login_redirect_port),
+        )
+        .subcommand(
+            SubCommand::with_name(Other_commands::logout.as_ref())
+                .about("Revoke the session and remove the token of a context.")
+                .arg(&logout_context_arg)
+                .arg(&logout_remove),
         )
         .subcommand(
             SubCommand::with_name(Other_commands::whoami.as_ref())
@@ -375,6 +851,27 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                         .clone()
                         .takes_value(false)
                         .help("print a valid bearer token for the drogue cloud instance."),
+                )
+                .arg(&output_arg),
+        )
+        .subcommand(
+            SubCommand::with_name(Other_commands::token.as_ref())
+                .about("Manage the personal access tokens of the user.")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .arg(output_arg.clone().global(true))
+                .subcommand(
+                    SubCommand::with_name(Token_subcommands::create.as_ref())
+                        .about("Create a new access token. The token is only shown once.")
+                        .arg(&token_description),
+                )
+                .subcommand(
+                    SubCommand::with_name(Token_subcommands::list.as_ref())
+                        .about("List the access tokens."),
+                )
+                .subcommand(
+                    SubCommand::with_name(Token_subcommands::delete.as_ref())
+                        .about("Revoke an access token.")
+                        .arg(&token_prefix),
                 ),
         )
         .subcommand(
@@ -393,7 +890,12 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                 )
                 .subcommand(
                     SubCommand::with_name(Context_subcommands::show.as_ref())
-                        .about("Show full configuration file"),
+                        .about("Show full configuration file")
+                        .arg(
+                            Arg::with_name(Parameters::show_secrets.as_ref())
+                                .long(Parameters::show_secrets.as_ref())
+                                .help("Show the tokens and secrets instead of redacting them."),
+                        ),
                 )
                 .subcommand(
                     SubCommand::with_name("set-active")
@@ -420,6 +922,14 @@ pub fn parse_arguments() -> ArgMatches<'static> {
                                 .required(true)
                                 .help("The new context name"),
                         ),
+                )
+                .subcommand(
+                    SubCommand::with_name(Context_subcommands::encrypt.as_ref())
+                        .about("Encrypt the credentials file with a passphrase, or change the passphrase. The passphrase can be set with DRG_CREDENTIALS_KEY environment variable."),
+                )
+                .subcommand(
+                    SubCommand::with_name(Context_subcommands::decrypt.as_ref())
+                        .about("Store the credentials file unencrypted."),
                 ),
         )
         .subcommand(
@@ -447,6 +957,20 @@ pub fn parse_arguments() -> ArgMatches<'static> {
         .get_matches()
 }
 
+/// The output format, --output is an option of the commands printing resources.
+pub fn get_output_format(matches: &ArgMatches) -> Result<OutputFormat> {
+    let mut matches = matches;
+    loop {
+        if let Some(output) = matches.value_of(Parameters::output) {
+            return OutputFormat::from(Some(output));
+        }
+        match matches.subcommand() {
+            (_, Some(submatches)) => matches = submatches,
+            _ => return Ok(OutputFormat::Default),
+        }
+    }
+}
+
 pub fn get_app_id<'a>(matches: &'a ArgMatches, config: &'a Context) -> Result<AppId> {
     match matches.value_of(Resources::app) {
         Some(a) => Ok(a.to_string()),
@@ -454,7 +978,8 @@ pub fn get_app_id<'a>(matches: &'a ArgMatches, config: &'a Context) -> Result<Ap
             .default_app
             .as_ref()
             .map(|v| {
-                println!("Using default app \"{}\".", &v);
+                // keep stdout clean for the outputs meant to be piped
+                eprintln!("Using default app \"{}\".", &v);
                 v.to_string()
             })
             .ok_or_else(|| {
@@ -462,3 +987,131 @@ pub fn get_app_id<'a>(matches: &'a ArgMatches, config: &'a Context) -> Result<Ap
             }),
     }
 }
+
+pub fn get_login_method<'a>(matches: &'a ArgMatches) -> Result<LoginMethod<'a>> {
+    if let Some(credentials) = get_client_credentials(matches)? {
+        return Ok(LoginMethod::ClientCredentials(credentials));
+    }
+    if let Some(access_token) = matches.value_of(Parameters::access_token) {
+        // clap validated the format already.
+        let (username, token) = access_token.split_once(':').unwrap();
+        return Ok(LoginMethod::AccessToken(AccessToken {
+            username: username.to_string(),
+            token: token.to_string(),
+        }));
+    }
+
+    Ok(match matches.value_of(Other_commands::token) {
+        Some(refresh_token) => LoginMethod::RefreshToken(refresh_token),
+        None if matches.is_present(Parameters::device_code) => LoginMethod::DeviceCode,
+        None => LoginMethod::Browser(get_redirect_address(matches)),
+    })
+}
+
+fn get_redirect_address(matches: &ArgMatches) -> RedirectAddress {
+    let default = RedirectAddress::default();
+    RedirectAddress {
+        host: matches
+            .value_of(Parameters::redirect_host)
+            .map_or(default.host, |h| h.to_string()),
+        // clap validated the port already.
+        port: matches
+            .value_of(Parameters::redirect_port)
+            .map_or(default.port, |p| p.parse().unwrap()),
+    }
+}
+
+/// The openid-connect client to log in with, if it's not the default one.
+pub fn get_oauth_client(matches: &ArgMatches) -> Option<OAuthClient> {
+    if !matches.is_present(Parameters::oidc_client_id) && !matches.is_present(Parameters::scope) {
+        return None;
+    }
+
+    let mut client = OAuthClient::default();
+    if let Some(id) = matches.value_of(Parameters::oidc_client_id) {
+        client.client_id = id.to_string();
+    }
+    client.client_secret = matches
+        .value_of(Parameters::oidc_client_secret)
+        .map(|s| s.to_string());
+    client.scopes = matches
+        .values_of(Parameters::scope)
+        .map(|v| v.map(|s| s.to_string()).collect())
+        .unwrap_or_default();
+    Some(client)
+}
+
+fn get_client_credentials(matches: &ArgMatches) -> Result<Option<ClientCredentials>> {
+    let client_id = match matches.value_of(Parameters::client_id) {
+        Some(id) => id.to_string(),
+        None => return Ok(None),
+    };
+
+    let client_secret = match matches.value_of(Parameters::client_secret_file) {
+        Some(file) => fs::read_to_string(file)
+            .context(format!("Unable to read the client secret from {}", file))?
+            .trim()
+            .to_string(),
+        None => matches
+            .value_of(Parameters::client_secret)
+            .map(|s| s.to_string())
+            .ok_or_else(|| {
+                anyhow!("Missing client secret, use --client-secret, --client-secret-file or DRG_CLIENT_SECRET.")
+            })?,
+    };
+
+    Ok(Some(ClientCredentials {
+        client_id,
+        client_secret,
+    }))
+}
+
+/// The value given to `set`, or `None` when it is generated.
+pub fn get_set_value(
+    matches: &ArgMatches,
+    value: Option<String>,
+    name: &str,
+) -> Result<Option<String>> {
+    match (value, matches.is_present(Parameters::generate)) {
+        (Some(value), false) => Ok(Some(value)),
+        (None, true) => Ok(None),
+        (Some(_), true) => Err(anyhow!("A {} cannot be given with --generate", name)),
+        (None, false) => Err(anyhow!("Missing {}, or --generate", name)),
+    }
+}
+
+/// The credential to add to a device, clap makes sure exactly one kind is given.
+pub fn get_credential(matches: &ArgMatches) -> Result<Credential> {
+    if let Some(password) = matches.value_of(Parameters::password) {
+        let password = devices::hash_password(password, matches.value_of(Parameters::hash))?;
+        Ok(match matches.value_of(Set_args::username) {
+            Some(username) => Credential::UsernamePassword {
+                username: username.to_string(),
+                password,
+                unique: matches.is_present(Parameters::unique),
+            },
+            None => Credential::Password(password),
+        })
+    } else if let Some(key) = matches.value_of(Parameters::psk) {
+        let key = devices::parse_psk(key, matches.is_present(Parameters::hex))?;
+        Ok(Credential::PreSharedKey(PreSharedKey {
+            key: base64::encode(key),
+            extra: Default::default(),
+        }))
+    } else if let Some(alias) = matches.value_of(Parameters::cert_alias) {
+        Ok(Credential::Certificate(alias.to_string()))
+    } else {
+        Err(anyhow!("Missing credential argument"))
+    }
+}
+
+pub fn get_credential_selector(matches: &ArgMatches) -> Result<CredentialSelector> {
+    match (
+        matches.value_of(Parameters::index),
+        matches.value_of(Parameters::credential_type),
+    ) {
+        (Some(index), _) => Ok(CredentialSelector::Index(index.parse()?)),
+        (None, Some(t)) => Ok(CredentialSelector::Type(t.to_string())),
+        (None, None) => Err(anyhow!("Missing --index or --type argument")),
+    }
+}
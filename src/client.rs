@@ -0,0 +1,238 @@
+use crate::config::{Context, Token};
+use crate::models::{AccessTokenInfo, Application, CreatedAccessToken, Device};
+use oauth2::TokenResponse;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::{StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub const API_PATH: &str = "api/registry/v1alpha1";
+pub const TOKENS_API_PATH: &str = "api/tokens/v1alpha1";
+
+/// The error payload returned by drogue-cloud.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorInformation {
+    /// A machine readable error code, e.g. `NotFound` or `Conflict`.
+    pub error: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("Error while sending the request")]
+    Request(#[from] reqwest::Error),
+    #[error("Invalid response from the server")]
+    Response(#[source] reqwest::Error),
+    #[error("Resource not found")]
+    NotFound,
+    #[error("{} ({}): {}", .error.error, .status, .error.message)]
+    Service {
+        status: StatusCode,
+        error: ErrorInformation,
+    },
+}
+
+impl ClientError {
+    /// The update was rejected because the resource was modified since it was read.
+    pub fn is_conflict(&self) -> bool {
+        matches!(self, ClientError::Service { status, .. } if *status == StatusCode::CONFLICT)
+    }
+}
+
+pub type ClientResult<T> = Result<T, ClientError>;
+
+/// A blocking client for the apps and devices of the drogue-cloud registry.
+///
+/// The client uses the token of the context as is, refreshing it is up to the caller,
+/// see [`crate::openid::verify_token_validity`].
+pub struct RegistryClient {
+    client: Client,
+    registry_url: Url,
+    token: Option<Token>,
+}
+
+impl RegistryClient {
+    pub fn new(context: &Context) -> Self {
+        RegistryClient {
+            client: Client::new(),
+            registry_url: context.registry_url.clone(),
+            token: context.token.clone(),
+        }
+    }
+
+    pub fn list_apps(&self, labels: Option<&str>) -> ClientResult<Vec<Application>> {
+        let req = self.client.get(self.apps_url(None));
+        self.list(req, labels)
+    }
+
+    pub fn get_app(&self, app: &str) -> ClientResult<Application> {
+        let res = self.send(self.client.get(self.apps_url(Some(app))))?;
+        read_json(res)
+    }
+
+    pub fn create_app(&self, app: &Application) -> ClientResult<()> {
+        self.send(self.client.post(self.apps_url(None)).json(app))
+            .map(|_| ())
+    }
+
+    pub fn update_app(&self, name: &str, app: &Application) -> ClientResult<()> {
+        self.send(self.client.put(self.apps_url(Some(name))).json(app))
+            .map(|_| ())
+    }
+
+    pub fn delete_app(&self, app: &str) -> ClientResult<()> {
+        self.send(self.client.delete(self.apps_url(Some(app))))
+            .map(|_| ())
+    }
+
+    pub fn list_devices(&self, app: &str, labels: Option<&str>) -> ClientResult<Vec<Device>> {
+        let req = self.client.get(self.devices_url(app, None));
+        self.list(req, labels)
+    }
+
+    pub fn get_device(&self, app: &str, device: &str) -> ClientResult<Device> {
+        let res = self.send(self.client.get(self.devices_url(app, Some(device))))?;
+        read_json(res)
+    }
+
+    pub fn create_device(&self, app: &str, device: &Device) -> ClientResult<()> {
+        self.send(self.client.post(self.devices_url(app, None)).json(device))
+            .map(|_| ())
+    }
+
+    pub fn update_device(&self, app: &str, name: &str, device: &Device) -> ClientResult<()> {
+        self.send(
+            self.client
+                .put(self.devices_url(app, Some(name)))
+                .json(device),
+        )
+        .map(|_| ())
+    }
+
+    pub fn delete_device(&self, app: &str, device: &str) -> ClientResult<()> {
+        self.send(self.client.delete(self.devices_url(app, Some(device))))
+            .map(|_| ())
+    }
+
+    fn apps_url(&self, app: Option<&str>) -> String {
+        let app = match app {
+            Some(app) => format!("/{}", app),
+            None => String::new(),
+        };
+        format!("{}{}/apps{}", self.registry_url, API_PATH, app)
+    }
+
+    fn devices_url(&self, app: &str, device: Option<&str>) -> String {
+        let device = match device {
+            Some(dev) => format!("/{}", dev),
+            None => String::new(),
+        };
+        format!("{}/devices{}", self.apps_url(Some(app)), device)
+    }
+
+    fn list<T: DeserializeOwned>(
+        &self,
+        mut req: RequestBuilder,
+        labels: Option<&str>,
+    ) -> ClientResult<Vec<T>> {
+        if let Some(labels) = labels {
+            req = req.query(&[("labels", labels)]);
+        }
+        read_json(self.send(req)?)
+    }
+
+    fn send(&self, req: RequestBuilder) -> ClientResult<Response> {
+        send(req, self.token.as_ref())
+    }
+}
+
+/// A blocking client for the personal access tokens API of drogue-cloud.
+pub struct TokenClient {
+    client: Client,
+    api_url: Url,
+    token: Option<Token>,
+}
+
+impl TokenClient {
+    pub fn new(context: &Context) -> Self {
+        TokenClient {
+            client: Client::new(),
+            api_url: context.drogue_cloud_url.clone(),
+            token: context.token.clone(),
+        }
+    }
+
+    pub fn list_tokens(&self) -> ClientResult<Vec<AccessTokenInfo>> {
+        read_json(send(
+            self.client.get(self.tokens_url(None)),
+            self.token.as_ref(),
+        )?)
+    }
+
+    pub fn create_token(&self, description: Option<&str>) -> ClientResult<CreatedAccessToken> {
+        let mut req = self.client.post(self.tokens_url(None));
+        if let Some(description) = description {
+            req = req.query(&[("description", description)]);
+        }
+        read_json(send(req, self.token.as_ref())?)
+    }
+
+    pub fn delete_token(&self, prefix: &str) -> ClientResult<()> {
+        send(
+            self.client.delete(self.tokens_url(Some(prefix))),
+            self.token.as_ref(),
+        )
+        .map(|_| ())
+    }
+
+    fn tokens_url(&self, prefix: Option<&str>) -> String {
+        let prefix = match prefix {
+            Some(prefix) => format!("/{}", prefix),
+            None => String::new(),
+        };
+        format!("{}{}{}", self.api_url, TOKENS_API_PATH, prefix)
+    }
+}
+
+// Send the request, any unsuccessful status is turned into an error.
+fn send(req: RequestBuilder, token: Option<&Token>) -> ClientResult<Response> {
+    let res = authenticate(req, token).send()?;
+
+    match res.status() {
+        s if s.is_success() => Ok(res),
+        StatusCode::NOT_FOUND => Err(ClientError::NotFound),
+        status => Err(ClientError::Service {
+            status,
+            error: error_information(status, res),
+        }),
+    }
+}
+
+// Use the error payload of the response if there is one, the status otherwise.
+fn error_information(status: StatusCode, res: Response) -> ErrorInformation {
+    let reason = status
+        .canonical_reason()
+        .unwrap_or("Unknown")
+        .replace(' ', "");
+    let body = res.text().unwrap_or_default();
+
+    serde_json::from_str(&body).unwrap_or(ErrorInformation {
+        error: reason,
+        message: body,
+    })
+}
+
+/// Add the credentials of a context to a request.
+pub fn authenticate(req: RequestBuilder, token: Option<&Token>) -> RequestBuilder {
+    match token {
+        Some(Token::TokenResponse(token)) => req.bearer_auth(token.access_token().secret()),
+        Some(Token::AccessToken(token)) => req.basic_auth(&token.username, Some(&token.token)),
+        None => req,
+    }
+}
+
+fn read_json<T: DeserializeOwned>(res: Response) -> ClientResult<T> {
+    res.json().map_err(ClientError::Response)
+}
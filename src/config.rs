@@ -1,12 +1,18 @@
 use anyhow::{anyhow, Context as AnyhowContext, Result};
+use fs2::FileExt;
 use serde::{Deserialize, Serialize};
-use std::{env, fs::create_dir_all, fs::write, fs::File, path::Path, process::exit};
+use serde_yaml::Value;
+use std::fs::{self, create_dir_all, File, OpenOptions};
+use std::io::Write;
+use std::{env, path::Path};
 
+use crate::credentials::{self, Key, Secrets};
 use crate::AppId;
 use chrono::{DateTime, Utc};
 use dirs::config_dir;
 use oauth2::basic::BasicTokenResponse;
-use tabular::{Row, Table};
+use oauth2::TokenResponse;
+use tempfile::NamedTempFile;
 use url::Url;
 
 pub type ContextId = String;
@@ -15,6 +21,9 @@ pub type ContextId = String;
 pub struct Config {
     pub active_context: ContextId,
     pub contexts: Vec<Context>,
+    // set when the credentials file is encrypted.
+    #[serde(skip)]
+    credentials_key: Option<Key>,
     //todo : when loading, put a ref to the active context for faster access
     // to avoid looping through the contexts each time.
     // #[serde(skip)]
@@ -29,8 +38,73 @@ pub struct Context {
     pub auth_url: Url,
     pub token_url: Url,
     pub registry_url: Url,
-    pub token_exp_date: DateTime<Utc>,
-    pub token: BasicTokenResponse,
+    // access tokens don't expire.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_exp_date: Option<DateTime<Utc>>,
+    // cleared on logout. The secrets are saved in the credentials file, see `credentials`.
+    #[serde(default, skip_serializing)]
+    pub token: Option<Token>,
+    // Set for service accounts, new tokens are requested with them when the token expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_credentials: Option<ClientCredentials>,
+    // The openid-connect client used to log in, when it is not the default one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oauth_client: Option<OAuthClient>,
+}
+
+/// The credentials sent with the requests.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Token {
+    /// An openid-connect token, sent as a bearer token.
+    TokenResponse(BasicTokenResponse),
+    /// A drogue-cloud access token, sent with basic auth.
+    AccessToken(AccessToken),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccessToken {
+    pub username: String,
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClientCredentials {
+    pub client_id: String,
+    #[serde(default, skip_serializing)]
+    pub client_secret: String,
+}
+
+/// The openid-connect client drg logs in and refreshes tokens with.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OAuthClient {
+    pub client_id: String,
+    #[serde(default, skip_serializing)]
+    pub client_secret: Option<String>,
+    /// Requested in addition to `offline_access`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+}
+
+impl Default for OAuthClient {
+    fn default() -> Self {
+        OAuthClient {
+            client_id: "drogue".to_string(),
+            client_secret: None,
+            scopes: Vec::new(),
+        }
+    }
+}
+
+/// An exclusive lock on the configuration file, released when dropped.
+pub struct ConfigLock {
+    file: File,
+}
+
+impl Drop for ConfigLock {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.file);
+    }
 }
 
 impl Config {
@@ -38,40 +112,109 @@ impl Config {
         Config {
             active_context: String::new(),
             contexts: Vec::new(),
+            credentials_key: None,
         }
     }
     pub fn from(path: Option<&str>) -> Result<Config> {
-        let path = eval_config_path(path);
+        Config::load(path, None)
+    }
+
+    /// Read the configuration file again, e.g. to save a change made by a long running command.
+    /// The credentials are decrypted with the key of this configuration while it is still the same.
+    pub fn reload(&self, path: Option<&str>) -> Result<Config> {
+        Config::load(path, self.credentials_key.as_ref())
+    }
+
+    fn load(path: Option<&str>, key: Option<&Key>) -> Result<Config> {
+        let path = eval_config_path(path)?;
         log::info!("Loading configuration file: {}", &path);
 
-        let file = File::open(path).context(
+        let file = File::open(&path).context(
             "Unable to open configuration file. Did you log into a drogue cloud cluster ?",
         )?;
-        let config: Config =
+        let mut config: Config =
             serde_yaml::from_reader(file).context("Invalid configuration file.")?;
 
+        // older versions saved the secrets in the configuration file.
+        let inline_secrets = config.contexts.iter().any(Context::has_secrets);
+
+        if let Some(mut credentials) = credentials::read(&credentials::path(&path), key)? {
+            for context in &mut config.contexts {
+                if let Some(secrets) = credentials.secrets.remove(&context.name) {
+                    context.set_secrets(secrets);
+                }
+            }
+            config.credentials_key = credentials.key;
+        }
+
+        if inline_secrets {
+            log::warn!("Moving the secrets of the configuration file to the credentials file.");
+            // the previous version holds the secrets, it is not backed up.
+            if let Err(e) = config.save(&path, false) {
+                log::warn!("Unable to move the secrets to the credentials file: {}", e);
+            }
+        }
+
         Ok(config)
     }
 
-    pub fn add_context(&mut self, mut context: Context) -> Result<()> {
+    /// Lock the configuration file, so that parallel drg processes don't refresh the same token
+    /// or overwrite each other's changes. The lock is meant to be held from reading the file to writing it.
+    pub fn lock(path: Option<&str>) -> Result<ConfigLock> {
+        let path = format!("{}.lock", eval_config_path(path)?);
+        if let Some(parent) = Path::new(&path).parent() {
+            create_dir_all(parent).context("Failed to create parent directory of configuration")?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .context(format!("Unable to open lock file :{}", path))?;
+        if file.try_lock_exclusive().is_err() {
+            log::info!("Waiting for another drg process to release {}", path);
+            file.lock_exclusive()
+                .context(format!("Unable to lock config file :{}", path))?;
+        }
+        Ok(ConfigLock { file })
+    }
+
+    /// Save the token of a context refreshed after the configuration was loaded.
+    /// The file is read again under the lock, to keep the changes made by other drg processes.
+    pub fn save_token(&self, path: Option<&str>, context: &Context) -> Result<()> {
+        let _lock = Config::lock(path)?;
+        let mut config = self.reload(path)?;
+
+        let saved = config.get_context_mut(&Some(context.name.clone()))?;
+        saved.token = context.token.clone();
+        saved.token_exp_date = context.token_exp_date;
+        config.write(path)
+    }
+
+    /// Whether there is a configuration file, a login starts a new one otherwise.
+    pub fn exists(path: Option<&str>) -> Result<bool> {
+        Ok(Path::new(&eval_config_path(path)?).exists())
+    }
+
+    /// Add the context, or replace the one with the same name. Returns true if it was replaced.
+    pub fn add_context(&mut self, mut context: Context) -> Result<bool> {
         let name = &context.name;
         if !self.contains_context(name) {
             if self.contexts.is_empty() {
                 self.active_context = name.clone();
             }
             self.contexts.push(context);
-            Ok(())
+            Ok(false)
         } else {
             context.default_app = self.get_context(&Some(name.clone()))?.default_app.clone();
             self.replace_context(context)?;
-            Ok(())
+            Ok(true)
         }
     }
 
     fn replace_context(&mut self, context: Context) -> Result<()> {
-        let name = &context.name;
-        self.delete_context(&name)?;
-        println!("Updated existing context {}", &name);
+        self.delete_context(&context.name)?;
         self.contexts.push(context);
         Ok(())
     }
@@ -123,41 +266,8 @@ impl Config {
         }
         false
     }
-    pub fn list_contexts(&self) {
-        let mut table = Table::new("{:<}  {:<}  {:<}");
-        table.add_row(
-            Row::new()
-                .with_cell("NAME")
-                .with_cell("ADDRESS")
-                .with_cell("DEFAULT APP"),
-        );
-
-        for config in &self.contexts {
-            let name;
-            if self.active_context == config.name {
-                name = format!("{} *", config.name);
-            } else {
-                name = config.name.clone();
-            }
-            table.add_row(
-                Row::new()
-                    .with_cell(&name)
-                    .with_cell(&config.drogue_cloud_url)
-                    .with_cell(
-                        &config
-                            .default_app
-                            .as_ref()
-                            .unwrap_or(&"<Not Set>".to_string()),
-                    ),
-            );
-        }
-
-        print!("{}", table);
-    }
-
     pub fn set_active_context(&mut self, name: ContextId) -> Result<()> {
         if self.contains_context(&name) {
-            println!("Switched active context to: {}", &name);
             self.active_context = name;
             Ok(())
         } else {
@@ -166,18 +276,48 @@ impl Config {
     }
 
     pub fn write(&self, path: Option<&str>) -> Result<()> {
-        let path = eval_config_path(path);
+        self.save(&eval_config_path(path)?, true)
+    }
+
+    fn save(&self, path: &str, backup: bool) -> Result<()> {
         if let Some(parent) = Path::new(&path).parent() {
             create_dir_all(parent).context("Failed to create parent directory of configuration")?;
         }
 
         log::info!("Saving config file: {}", &path);
-        write(&path, serde_yaml::to_string(&self)?)
-            .context(format!("Unable to write config file :{}", path))
+        replace_file(
+            Path::new(&path),
+            serde_yaml::to_string(&self)?.as_bytes(),
+            false,
+            backup,
+        )
+        .context(format!("Unable to write config file :{}", path))?;
+
+        let secrets = self
+            .contexts
+            .iter()
+            .map(|c| (c.name.clone(), c.secrets()))
+            .collect();
+        credentials::write(
+            &credentials::path(path),
+            secrets,
+            self.credentials_key.as_ref(),
+        )
+    }
+
+    /// Encrypt the credentials file with a new passphrase, read from the terminal or the environment.
+    pub fn encrypt_credentials(&mut self) -> Result<()> {
+        self.credentials_key = Some(Key::new(&credentials::passphrase(true)?)?);
+        Ok(())
+    }
+
+    /// Store the credentials in plain text, the file is still only readable by the user.
+    pub fn decrypt_credentials(&mut self) {
+        self.credentials_key = None;
     }
 
     pub fn delete_context(&mut self, name: &str) -> Result<()> {
-        if self.contains_context(&name) {
+        if self.contains_context(name) {
             self.contexts.retain(|c| c.name != name);
 
             if self.active_context == name {
@@ -210,10 +350,25 @@ impl Config {
         }
     }
 
-    //todo implement a display Trait ?
-    pub fn show(&self) -> Result<()> {
-        println!("{}", serde_yaml::to_string(&self)?);
-        Ok(())
+    /// The configuration as YAML, the secrets are redacted unless `reveal` is set.
+    pub fn to_yaml(&self, reveal: bool) -> Result<String> {
+        let mut value = serde_yaml::to_value(self)?;
+        if let Some(contexts) = value["contexts"].as_sequence_mut() {
+            for (value, context) in contexts.iter_mut().zip(&self.contexts) {
+                context.show_secrets(value, reveal)?;
+            }
+        }
+        Ok(serde_yaml::to_string(&value)?)
+    }
+}
+
+impl Token {
+    /// The secret part of the token.
+    pub fn secret(&self) -> &str {
+        match self {
+            Token::TokenResponse(token) => token.access_token().secret(),
+            Token::AccessToken(token) => &token.token,
+        }
     }
 }
 
@@ -225,23 +380,203 @@ impl Context {
     pub fn set_default_app(&mut self, app: AppId) {
         self.default_app = Some(app);
     }
+
+    fn has_secrets(&self) -> bool {
+        let secrets = self.secrets();
+        secrets.token.is_some()
+            || secrets.client_secret.is_some()
+            || secrets.oauth_client_secret.is_some()
+    }
+
+    fn secrets(&self) -> Secrets {
+        Secrets {
+            token: self.token.clone(),
+            client_secret: self
+                .client_credentials
+                .as_ref()
+                .map(|c| c.client_secret.clone())
+                .filter(|s| !s.is_empty()),
+            oauth_client_secret: self
+                .oauth_client
+                .as_ref()
+                .and_then(|c| c.client_secret.clone()),
+        }
+    }
+
+    fn set_secrets(&mut self, secrets: Secrets) {
+        if secrets.token.is_some() {
+            self.token = secrets.token;
+        }
+        if let (Some(credentials), Some(secret)) =
+            (&mut self.client_credentials, secrets.client_secret)
+        {
+            credentials.client_secret = secret;
+        }
+        if let (Some(client), Some(secret)) = (&mut self.oauth_client, secrets.oauth_client_secret)
+        {
+            client.client_secret = Some(secret);
+        }
+    }
+
+    // Add the secrets to the serialized context.
+    fn show_secrets(&self, value: &mut Value, reveal: bool) -> Result<()> {
+        let secret = |v: Value| {
+            if reveal {
+                v
+            } else {
+                Value::String("<redacted>".to_string())
+            }
+        };
+
+        let secrets = self.secrets();
+        if let Some(token) = secrets.token {
+            value["token"] = secret(serde_yaml::to_value(token)?);
+        }
+        if let Some(s) = secrets.client_secret {
+            value["client_credentials"]["client_secret"] = secret(Value::String(s));
+        }
+        if let Some(s) = secrets.oauth_client_secret {
+            value["oauth_client"]["client_secret"] = secret(Value::String(s));
+        }
+        Ok(())
+    }
+}
+
+// Write a temporary file and rename it over the previous version, so that the file is never
+// partially written. With `backup`, the previous version is kept in a .bak file.
+// Private files are only readable by the user, others keep the permissions of the previous version.
+pub(crate) fn replace_file(path: &Path, content: &[u8], private: bool, backup: bool) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    // only readable by the user until the permissions are set.
+    let mut file = NamedTempFile::new_in(dir)?;
+
+    if path.exists() {
+        if !private {
+            fs::set_permissions(file.path(), fs::metadata(path)?.permissions())?;
+        }
+        if backup {
+            let mut backup = path.as_os_str().to_owned();
+            backup.push(".bak");
+            fs::copy(path, backup)?;
+        }
+    }
+
+    file.write_all(content)?;
+    file.as_file().sync_all()?;
+    file.persist(path)?;
+    Ok(())
 }
 
 // use the provided config path or `$DRGCFG` value if set
 // otherwise will default to $XDG_CONFIG_HOME
 // fall back to `$HOME/.config` if XDG var is not set.
-fn eval_config_path(path: Option<&str>) -> String {
+fn eval_config_path(path: Option<&str>) -> Result<String> {
     match path {
-        Some(p) => p.to_string(),
-        None => env::var("DRGCFG").unwrap_or_else(|_| {
-            let xdg = match config_dir() {
-                Some(path) => path.into_os_string().into_string().unwrap(),
-                None => {
-                    log::error!("Error accessing config file, please try using --config");
-                    exit(1);
-                }
-            };
-            format!("{}/drg_config.yaml", xdg)
-        }),
+        Some(p) => Ok(p.to_string()),
+        None => match env::var("DRGCFG") {
+            Ok(path) => Ok(path),
+            Err(_) => {
+                let xdg = config_dir()
+                    .and_then(|path| path.into_os_string().into_string().ok())
+                    .ok_or_else(|| {
+                        anyhow!("Error accessing config file, please try using --config")
+                    })?;
+                Ok(format!("{}/drg_config.yaml", xdg))
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openid;
+    use tempfile::{tempdir, TempDir};
+
+    const SECRET: &str = "drg_secret";
+
+    // a configuration file written by an older version, with the token inline.
+    fn legacy_config() -> Result<(TempDir, String)> {
+        let dir = tempdir()?;
+        let path = dir.path().join("drg_config.yaml");
+        fs::write(
+            &path,
+            format!(
+                r#"active_context: default
+contexts:
+  - name: default
+    drogue_cloud_url: "https://api.example.com/"
+    default_app: ~
+    auth_url: "https://sso.example.com/auth"
+    token_url: "https://sso.example.com/token"
+    registry_url: "https://registry.example.com/"
+    token:
+      username: alice
+      token: {}
+"#,
+                SECRET
+            ),
+        )?;
+        Ok((dir, path.to_string_lossy().to_string()))
+    }
+
+    // every file next to the configuration, with the ones containing the secret.
+    fn files_with_secret(dir: &TempDir) -> Result<Vec<String>> {
+        let mut found = Vec::new();
+        for entry in fs::read_dir(dir.path())? {
+            let path = entry?.path();
+            if fs::read_to_string(&path)?.contains(SECRET) {
+                found.push(path.file_name().unwrap().to_string_lossy().to_string());
+            }
+        }
+        Ok(found)
+    }
+
+    #[test]
+    fn no_secrets_in_backup_after_migration() -> Result<()> {
+        let (dir, path) = legacy_config()?;
+
+        let config = Config::from(Some(&path))?;
+        assert_eq!(
+            config.contexts[0].token.as_ref().map(Token::secret),
+            Some(SECRET)
+        );
+
+        assert_eq!(
+            files_with_secret(&dir)?,
+            vec!["drg_config.credentials.yaml".to_string()]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn no_plain_secrets_in_backup_after_encryption() -> Result<()> {
+        let (dir, path) = legacy_config()?;
+        let mut config = Config::from(Some(&path))?;
+
+        config.credentials_key = Some(Key::new("passphrase")?);
+        config.write(Some(&path))?;
+
+        assert!(files_with_secret(&dir)?.is_empty());
+        assert!(!Path::new(&format!("{}.bak", credentials::path(&path).display())).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn no_secrets_in_backup_after_logout() -> Result<()> {
+        let (dir, path) = legacy_config()?;
+        let mut config = Config::from(Some(&path))?;
+        // a regular write, the config file is backed up.
+        config.write(Some(&path))?;
+
+        openid::logout(config.get_context_mut(&None)?)?;
+        config.write(Some(&path))?;
+
+        assert!(files_with_secret(&dir)?.is_empty());
+        assert!(Path::new(&format!("{}.bak", path)).exists());
+        Ok(())
     }
 }
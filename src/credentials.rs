@@ -0,0 +1,315 @@
+//! The secrets of the contexts are stored in a credentials file next to the configuration file,
+//! only readable by the user and optionally encrypted with a passphrase.
+//! The file is written with the configuration file, under the same lock.
+
+use crate::config::{self, ContextId, Token};
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use argon2::Argon2;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// When set, the passphrase of the credentials file is read from this variable instead of the terminal.
+pub const PASSPHRASE_ENV_VAR: &str = "DRG_CREDENTIALS_KEY";
+
+/// The secrets of a context.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Secrets {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<Token>,
+    /// The secret of the service account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    /// The secret of the openid-connect client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oauth_client_secret: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CredentialsFile {
+    Plain(BTreeMap<ContextId, Secrets>),
+    // the secrets are serialized as YAML before being encrypted.
+    Encrypted {
+        salt: String,
+        nonce: String,
+        data: String,
+    },
+}
+
+/// The key of an encrypted credentials file.
+#[derive(Clone)]
+pub struct Key {
+    salt: Vec<u8>,
+    key: [u8; 32],
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(<redacted>)")
+    }
+}
+
+impl Key {
+    /// Derive a key from the passphrase, with a new salt.
+    pub fn new(passphrase: &str) -> Result<Key> {
+        let mut salt = vec![0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        Key::derive(passphrase, salt)
+    }
+
+    fn derive(passphrase: &str, salt: Vec<u8>) -> Result<Key> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| anyhow!("Cannot derive the credentials key: {}", e))?;
+        Ok(Key { salt, key })
+    }
+}
+
+/// The content of the credentials file.
+pub struct Credentials {
+    pub secrets: BTreeMap<ContextId, Secrets>,
+    /// Set when the file is encrypted.
+    pub key: Option<Key>,
+}
+
+/// drg_config.yaml is stored along drg_config.credentials.yaml
+pub fn path(config_path: &str) -> PathBuf {
+    Path::new(config_path).with_extension("credentials.yaml")
+}
+
+/// Read the credentials file, if there is one.
+/// A known key is used as long as the file is encrypted with it, otherwise the passphrase is needed.
+pub fn read(path: &Path, key: Option<&Key>) -> Result<Option<Credentials>> {
+    read_with(path, key, || passphrase(false))
+}
+
+// the passphrase is only asked for when the file is encrypted.
+fn read_with<P: FnOnce() -> Result<String>>(
+    path: &Path,
+    known_key: Option<&Key>,
+    passphrase: P,
+) -> Result<Option<Credentials>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    log::info!("Loading credentials file: {}", path.display());
+
+    let content = fs::read_to_string(path).context(format!(
+        "Unable to read credentials file: {}",
+        path.display()
+    ))?;
+    let file: CredentialsFile =
+        serde_yaml::from_str(&content).context("Invalid credentials file.")?;
+
+    Ok(Some(match file {
+        CredentialsFile::Plain(secrets) => Credentials { secrets, key: None },
+        CredentialsFile::Encrypted { salt, nonce, data } => {
+            let salt = base64::decode(salt)?;
+            let key = match known_key {
+                Some(key) if key.salt == salt => key.clone(),
+                _ => Key::derive(&passphrase()?, salt)?,
+            };
+            let plain = Aes256Gcm::new(&key.key.into())
+                .decrypt(
+                    Nonce::from_slice(&base64::decode(nonce)?),
+                    base64::decode(data)?.as_ref(),
+                )
+                .map_err(|_| {
+                    anyhow!("Unable to decrypt the credentials file, wrong passphrase ?")
+                })?;
+
+            Credentials {
+                secrets: serde_yaml::from_slice(&plain).context("Invalid credentials file.")?,
+                key: Some(key),
+            }
+        }
+    }))
+}
+
+/// Write the credentials file, encrypted if a key is given.
+pub fn write(path: &Path, secrets: BTreeMap<ContextId, Secrets>, key: Option<&Key>) -> Result<()> {
+    let file = match key {
+        None => CredentialsFile::Plain(secrets),
+        Some(key) => {
+            let plain = serde_yaml::to_string(&secrets)?;
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let data = Aes256Gcm::new(&key.key.into())
+                .encrypt(&nonce, plain.as_bytes())
+                .map_err(|_| anyhow!("Unable to encrypt the credentials"))?;
+
+            CredentialsFile::Encrypted {
+                salt: base64::encode(&key.salt),
+                nonce: base64::encode(nonce),
+                data: base64::encode(data),
+            }
+        }
+    };
+
+    log::info!("Saving credentials file: {}", path.display());
+    // a backup would keep the secrets after a logout, or in plain text after an encryption.
+    config::replace_file(path, serde_yaml::to_string(&file)?.as_bytes(), true, false).context(
+        format!("Unable to write credentials file: {}", path.display()),
+    )
+}
+
+/// Read the passphrase from the environment, or ask for it.
+pub fn passphrase(confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV_VAR) {
+        return Ok(passphrase);
+    }
+
+    let passphrase =
+        rpassword::prompt_password("Passphrase of the drg credentials: ").context(format!(
+            "Unable to read the passphrase, it can be set with {}",
+            PASSPHRASE_ENV_VAR
+        ))?;
+    if passphrase.is_empty() {
+        return Err(anyhow!("The passphrase cannot be empty"));
+    }
+    if confirm && rpassword::prompt_password("Confirm the passphrase: ")? != passphrase {
+        return Err(anyhow!("The passphrases do not match"));
+    }
+    Ok(passphrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AccessToken;
+    use tempfile::tempdir;
+
+    fn secrets() -> BTreeMap<ContextId, Secrets> {
+        let mut secrets = BTreeMap::new();
+        secrets.insert(
+            "default".to_string(),
+            Secrets {
+                token: Some(Token::AccessToken(AccessToken {
+                    username: "alice".to_string(),
+                    token: "drg_secret".to_string(),
+                })),
+                client_secret: Some("client-secret".to_string()),
+                oauth_client_secret: None,
+            },
+        );
+        secrets
+    }
+
+    #[test]
+    fn encrypted_round_trip() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("drg_config.credentials.yaml");
+
+        write(&path, secrets(), Some(&Key::new("passphrase")?))?;
+
+        let content = fs::read_to_string(&path)?;
+        assert!(content.contains("encrypted:"));
+        assert!(!content.contains("drg_secret"));
+        assert!(!content.contains("client-secret"));
+
+        let credentials = read_with(&path, None, || Ok("passphrase".to_string()))?.unwrap();
+        assert!(credentials.key.is_some());
+        let secrets = &credentials.secrets["default"];
+        match &secrets.token {
+            Some(Token::AccessToken(token)) => assert_eq!(token.token, "drg_secret"),
+            other => panic!("unexpected token {:?}", other),
+        }
+        assert_eq!(secrets.client_secret.as_deref(), Some("client-secret"));
+        Ok(())
+    }
+
+    #[test]
+    fn new_salt_and_nonce_on_write() -> Result<()> {
+        let dir = tempdir()?;
+        let (first, second) = (
+            dir.path().join("first.yaml"),
+            dir.path().join("second.yaml"),
+        );
+        let key = Key::new("passphrase")?;
+
+        write(&first, secrets(), Some(&key))?;
+        write(&second, secrets(), Some(&key))?;
+        let read_file = |path: &Path| -> Result<CredentialsFile> {
+            Ok(serde_yaml::from_str(&fs::read_to_string(path)?)?)
+        };
+
+        match (read_file(&first)?, read_file(&second)?) {
+            (
+                CredentialsFile::Encrypted {
+                    salt: salt1,
+                    nonce: nonce1,
+                    data: data1,
+                },
+                CredentialsFile::Encrypted {
+                    salt: salt2,
+                    nonce: nonce2,
+                    data: data2,
+                },
+            ) => {
+                // the key is kept, a nonce is never reused with it.
+                assert_eq!(salt1, salt2);
+                assert_ne!(nonce1, nonce2);
+                assert_ne!(data1, data2);
+            }
+            _ => panic!("the files are not encrypted"),
+        }
+        assert_ne!(Key::new("passphrase")?.salt, key.salt);
+        Ok(())
+    }
+
+    #[test]
+    fn known_key() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("drg_config.credentials.yaml");
+        let key = Key::new("passphrase")?;
+
+        write(&path, secrets(), Some(&key))?;
+        let credentials = read_with(&path, Some(&key), || panic!("the key is known"))?.unwrap();
+        assert_eq!(
+            credentials.secrets["default"].client_secret.as_deref(),
+            Some("client-secret")
+        );
+
+        // the file was encrypted again, with a new passphrase.
+        write(&path, secrets(), Some(&Key::new("other")?))?;
+        assert!(read_with(&path, Some(&key), || Ok("passphrase".to_string())).is_err());
+        assert!(read_with(&path, Some(&key), || Ok("other".to_string())).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_passphrase() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("drg_config.credentials.yaml");
+
+        write(&path, secrets(), Some(&Key::new("passphrase")?))?;
+
+        let error = read_with(&path, None, || Ok("wrong".to_string()))
+            .err()
+            .expect("the file was decrypted with a wrong passphrase");
+        assert!(error.to_string().contains("wrong passphrase"));
+        Ok(())
+    }
+
+    #[test]
+    fn plain_round_trip() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("drg_config.credentials.yaml");
+
+        write(&path, secrets(), None)?;
+
+        let credentials = read_with(&path, None, || panic!("no passphrase is needed"))?.unwrap();
+        assert!(credentials.key.is_none());
+        assert_eq!(
+            credentials.secrets["default"].client_secret.as_deref(),
+            Some("client-secret")
+        );
+        Ok(())
+    }
+}
@@ -1,44 +1,46 @@
+use crate::client::{ClientError, RegistryClient};
 use crate::config::Context;
-use crate::{util, AppId, DeviceId, Verbs};
+use crate::models::{Credential, Device, DeviceSpec, Password, PreSharedKey};
+use crate::outputs::{self, OutputFormat};
+use crate::{util, AppId, DeviceId};
 use anyhow::{anyhow, Context as AnyhowContext, Result};
-use json_value_merge::Merge;
-use oauth2::TokenResponse;
-use reqwest::blocking::Client;
-use reqwest::blocking::Response;
-use reqwest::{StatusCode, Url};
-use serde_json::{from_str, json, Value};
-use std::process::exit;
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+use rand::Rng;
+use serde::Serialize;
+use serde_json::{from_value, json, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
 use tabular::{Row, Table};
 
-fn craft_url(base: &Url, app_id: &str, device_id: Option<&str>) -> String {
-    let device = match device_id {
-        Some(dev) => format!("/{}", dev),
-        None => String::new(),
-    };
-    format!(
-        "{}{}/apps/{}/devices{}",
-        base,
-        util::API_PATH,
-        app_id,
-        device
-    )
+const SET_ATTEMPTS: usize = 5;
+const MASK: &str = "******";
+const GENERATED_PASSWORD_LENGTH: usize = 32;
+
+/// How the gateways given to `set gateway` change the gateways of a device.
+pub enum GatewayUpdate {
+    Add,
+    Remove,
+    Replace,
 }
 
-pub fn delete(config: &Context, app: AppId, device_id: DeviceId) -> Result<()> {
-    let client = Client::new();
-    let url = craft_url(&config.registry_url, &app, Some(&device_id));
+/// Selects the credentials to remove from a device.
+pub enum CredentialSelector {
+    /// The position of the credential, as listed by `get credentials`.
+    Index(usize),
+    /// All the credentials of a type, e.g. `pass`.
+    Type(String),
+}
 
-    client
-        .delete(&url)
-        .bearer_auth(&config.token.access_token().secret())
-        .send()
-        .context("Can't delete device.")
-        .map(|res| util::print_result(res, format!("Device {}", device_id), Verbs::delete))
+pub fn delete(config: &Context, app: AppId, device_id: DeviceId) -> Result<()> {
+    RegistryClient::new(config).delete_device(&app, &device_id)?;
+    println!("Device {} deleted.", device_id);
+    Ok(())
 }
 
-pub fn read(config: &Context, app: AppId, device_id: DeviceId) -> Result<()> {
-    get(&config, &app, &device_id)
-        .map(|res| util::print_result(res, device_id.to_string(), Verbs::get))
+pub fn read(config: &Context, app: AppId, device_id: DeviceId, output: OutputFormat) -> Result<()> {
+    let device = RegistryClient::new(config).get_device(&app, &device_id)?;
+    outputs::show_object(device, output, pretty_list)
 }
 
 pub fn create(
@@ -48,215 +50,687 @@ pub fn create(
     app_id: AppId,
     file: Option<&str>,
 ) -> Result<()> {
-    let data = if data == json!({}) {
-        json!({"credentials": {}})
-    } else {
-        data
-    };
-
     let body = match file {
-        Some(f) => util::get_data_from_file(f)?,
+        Some(f) => from_value(util::get_data_from_file(f)?).context("Invalid device in file")?,
         None => {
-            json!({
-            "metadata": {
-                "name": device_id,
-                "application": app_id
-            },
-            "spec": data
-            })
+            let spec = if data == json!({}) {
+                DeviceSpec {
+                    credentials: Some(Default::default()),
+                    ..Default::default()
+                }
+            } else {
+                from_value(data).context("Invalid device spec")?
+            };
+            Device {
+                spec,
+                ..Device::new(app_id.clone(), device_id.clone())
+            }
         }
     };
 
-    let client = Client::new();
-    let url = craft_url(&config.registry_url, &app_id, None);
-
-    client
-        .post(&url)
-        .header(reqwest::header::CONTENT_TYPE, "application/json")
-        .bearer_auth(&config.token.access_token().secret())
-        .body(body.to_string())
-        .send()
-        .context("Can't create device.")
-        .map(|res| util::print_result(res, format!("Device {}", device_id), Verbs::create))
+    RegistryClient::new(config).create_device(&app_id, &body)?;
+    println!("Device {} created.", device_id);
+    Ok(())
 }
 
 pub fn edit(config: &Context, app: AppId, device_id: DeviceId, file: Option<&str>) -> Result<()> {
-    match file {
+    let client = RegistryClient::new(config);
+    let updated = match file {
         Some(f) => {
-            let data = util::get_data_from_file(f)?;
+            let data: Device =
+                from_value(util::get_data_from_file(f)?).context("Invalid device in file")?;
+            client.update_device(&app, &device_id, &data)?;
+            true
+        }
+        None => util::edit_resource(
+            || client.get_device(&app, &device_id),
+            |data| client.update_device(&app, &device_id, data),
+        )?,
+    };
+
+    if updated {
+        println!("Device {} updated.", device_id);
+    }
+    Ok(())
+}
+
+pub fn list(
+    config: &Context,
+    app: AppId,
+    labels: Option<String>,
+    output: OutputFormat,
+) -> Result<()> {
+    let devices = RegistryClient::new(config).list_devices(&app, labels.as_deref())?;
+    outputs::show_list(devices, output, pretty_list)
+}
+
+/// A device referenced as a gateway by other devices.
+#[derive(Serialize)]
+struct Gateway {
+    name: DeviceId,
+    devices: Vec<DeviceId>,
+}
 
-            put(&config, &app, &device_id, data)
-                .map(|res| util::print_result(res, format!("Device {}", device_id), Verbs::edit))
+pub fn list_gateways(config: &Context, app: AppId, tree: bool, output: OutputFormat) -> Result<()> {
+    if tree && output != OutputFormat::Default {
+        return Err(anyhow!("The tree view only supports the default output."));
+    }
+
+    let devices = RegistryClient::new(config).list_devices(&app, None)?;
+    let gateways = served_devices(&devices);
+
+    if tree {
+        let existing = devices.iter().map(|d| d.metadata.name.as_str()).collect();
+        gateways_tree(&gateways, &existing);
+        return Ok(());
+    }
+
+    let gateways: Vec<Gateway> = gateways
+        .into_iter()
+        .map(|(name, devices)| Gateway { name, devices })
+        .collect();
+    match output {
+        OutputFormat::Json => util::show_json(serde_json::to_string(&gateways)?),
+        OutputFormat::Yaml => print!("{}", serde_yaml::to_string(&gateways)?),
+        OutputFormat::Name => gateways.iter().for_each(|g| println!("{}", g.name)),
+        OutputFormat::Wide => gateways_table(&gateways, true),
+        OutputFormat::Default => gateways_table(&gateways, false),
+        OutputFormat::JsonPath(path) => {
+            outputs::print_jsonpath(&outputs::to_values(&gateways)?, &path)?
         }
-        None => {
-            //read device data
-            let res = get(&config, &app, &device_id);
-            match res {
-                Ok(r) => match r.status() {
-                    StatusCode::OK => {
-                        let body = r.text().unwrap_or_else(|_| "{}".to_string());
-                        let insert = util::editor(body)?;
-                        put(&config, &app, &device_id, insert).map(|p| {
-                            util::print_result(p, format!("Device {}", device_id), Verbs::edit)
-                        })
-                    }
-                    e => {
-                        log::error!("Error : could not retrieve device: {}", e);
-                        util::exit_with_code(e)
+        OutputFormat::Template(template) => {
+            outputs::print_template(&outputs::to_values(&gateways)?, &template)?
+        }
+    }
+    Ok(())
+}
+
+pub fn watch(
+    config: &Context,
+    app: AppId,
+    labels: Option<String>,
+    save_token: &dyn Fn(&Context) -> Result<()>,
+    output: OutputFormat,
+    interval: Duration,
+) -> Result<()> {
+    util::watch(
+        config,
+        |context| RegistryClient::new(context).list_devices(&app, labels.as_deref()),
+        save_token,
+        output,
+        interval,
+    )
+}
+
+pub fn set_gateways(
+    config: &Context,
+    app: AppId,
+    device_id: DeviceId,
+    gateways: Vec<DeviceId>,
+    update: GatewayUpdate,
+) -> Result<()> {
+    if !matches!(update, GatewayUpdate::Remove) {
+        validate_gateways(&RegistryClient::new(config), &app, &device_id, &gateways)?;
+    }
+
+    set(config, app, device_id, |device| {
+        let selector = device
+            .spec
+            .gateway_selector
+            .get_or_insert_with(Default::default);
+        let names = &mut selector.match_names;
+        match update {
+            GatewayUpdate::Add => {
+                for gateway in &gateways {
+                    if !names.contains(gateway) {
+                        names.push(gateway.clone());
                     }
-                },
-                Err(e) => {
-                    log::error!("Error : could not execute request: {}", e);
-                    exit(2)
                 }
             }
+            GatewayUpdate::Remove => names.retain(|g| !gateways.contains(g)),
+            GatewayUpdate::Replace => *names = gateways.clone(),
+        }
+
+        // without gateways, the selector goes away like with unset.
+        if names.is_empty() && selector.extra.is_empty() {
+            device.spec.gateway_selector = None;
+        }
+        Ok(())
+    })
+}
+
+pub fn unset_gateways(config: &Context, app: AppId, device_id: DeviceId) -> Result<()> {
+    set(config, app, device_id, |device| {
+        device.spec.gateway_selector = None;
+        Ok(())
+    })
+}
+
+// The gateways must be other devices of the same app.
+fn validate_gateways(
+    client: &RegistryClient,
+    app: &str,
+    device_id: &str,
+    gateways: &[DeviceId],
+) -> Result<()> {
+    for gateway in gateways {
+        if gateway == device_id {
+            return Err(anyhow!("Device {} cannot be its own gateway", device_id));
+        }
+        match client.get_device(app, gateway) {
+            Ok(_) => {}
+            Err(ClientError::NotFound) => {
+                return Err(anyhow!(ClientError::NotFound)
+                    .context(format!("Gateway {} does not exist in app {}", gateway, app)))
+            }
+            Err(e) => return Err(e.into()),
         }
     }
+    Ok(())
 }
 
-pub fn list(config: &Context, app: AppId, labels: Option<String>) -> Result<()> {
-    let client = Client::new();
-    let url = craft_url(&config.registry_url, &app, None);
+/// Set the password of the device, or of the given username.
+/// The previous password is replaced, so that it can't be used anymore once rotated.
+pub fn set_password(
+    config: &Context,
+    app: AppId,
+    device_id: DeviceId,
+    password: Password,
+    username: Option<&str>,
+) -> Result<()> {
+    set(config, app, device_id, |device| {
+        let credentials = &mut device
+            .spec
+            .credentials
+            .get_or_insert_with(Default::default)
+            .credentials;
+        let replaced = |c: &Credential| match (c, username) {
+            (Credential::Password(_), None) => true,
+            (Credential::UsernamePassword { username: u, .. }, Some(username)) => u == username,
+            _ => false,
+        };
+
+        // a unique username stays unique.
+        let unique = credentials
+            .iter()
+            .any(|c| replaced(c) && matches!(c, Credential::UsernamePassword { unique: true, .. }));
+        credentials.retain(|c| !replaced(c));
 
-    let mut req = client
-        .get(&url)
-        .bearer_auth(&config.token.access_token().secret());
+        credentials.push(match username {
+            Some(user) => Credential::UsernamePassword {
+                username: user.to_string(),
+                password: password.clone(),
+                unique,
+            },
+            None => Credential::Password(password.clone()),
+        });
+        Ok(())
+    })
+}
 
-    if let Some(labels) = labels {
-        req = req.query(&[("labels", labels)]);
+/// Hash the password with `bcrypt` or `sha512`, or keep it in plain text.
+pub fn hash_password(password: &str, hash: Option<&str>) -> Result<Password> {
+    match hash {
+        None => Ok(Password::Plain(password.to_string())),
+        Some("bcrypt") => Ok(Password::BCrypt(pwhash::bcrypt::hash(password)?)),
+        Some("sha512") => Ok(Password::Sha512(pwhash::sha512_crypt::hash(password)?)),
+        Some(other) => Err(anyhow!("Unsupported password hash: {}", other)),
     }
+}
+
+pub fn generate_password() -> String {
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .take(GENERATED_PASSWORD_LENGTH)
+        .map(char::from)
+        .collect()
+}
 
-    let res = req.send().context("Can't list devices");
+/// Set the pre-shared key of the device, the previous keys are replaced.
+pub fn set_psk(config: &Context, app: AppId, device_id: DeviceId, key: Vec<u8>) -> Result<()> {
+    let credential = Credential::PreSharedKey(PreSharedKey {
+        key: base64::encode(&key),
+        extra: Default::default(),
+    });
+    set(config, app.clone(), device_id.clone(), |device| {
+        let credentials = &mut device
+            .spec
+            .credentials
+            .get_or_insert_with(Default::default)
+            .credentials;
+        credentials.retain(|c| !matches!(c, Credential::PreSharedKey(_)));
+        credentials.push(credential.clone());
+        Ok(())
+    })?;
 
-    if let Ok(r) = res {
-        if r.status() == StatusCode::OK {
-            pretty_list(r.text()?)?;
-            Ok(())
-        } else {
-            Err(anyhow!("List operation failed with {}", r.status()))
-        }
+    // what the device needs to connect over DTLS/TLS-PSK.
+    println!("PSK identity: {}@{}", device_id, app);
+    println!("PSK (hex): {}", hex::encode(&key));
+    println!("PSK (base64): {}", base64::encode(&key));
+    Ok(())
+}
+
+/// Decode a base64 key, or a hex key.
+pub fn parse_psk(key: &str, hex: bool) -> Result<Vec<u8>> {
+    let key = if hex {
+        hex::decode(key).context("The key is not hex encoded")?
     } else {
-        Err(anyhow!("Error while requesting devices list."))
+        base64::decode(key).context("The key is not base64 encoded, use --hex for hex keys")?
+    };
+    if key.is_empty() {
+        return Err(anyhow!("The key cannot be empty"));
     }
+    Ok(key)
+}
+
+pub fn generate_psk(length: usize) -> Vec<u8> {
+    let mut key = vec![0u8; length];
+    OsRng.fill(key.as_mut_slice());
+    key
 }
 
-pub fn set_gateway(
+/// Print the generated password, or write it to a file.
+pub fn show_generated_password(device_id: &str, password: &str, file: Option<&str>) -> Result<()> {
+    match file {
+        Some(file) => {
+            util::write_secret(file, password)?;
+            println!(
+                "The password of device {} was written to {}.",
+                device_id, file
+            );
+        }
+        None => {
+            println!("The generated password of device {} is:", device_id);
+            println!("{}", password);
+            println!("Make sure to save it, it won't be shown again.");
+        }
+    }
+    Ok(())
+}
+
+pub fn read_credentials(
     config: &Context,
     app: AppId,
     device_id: DeviceId,
-    gateway_id: DeviceId,
+    output: OutputFormat,
 ) -> Result<()> {
-    // prepare json data to merge
-    let data = json!({"spec": {
-    "gatewaySelector": {
-        "matchNames": [gateway_id]
+    let device = RegistryClient::new(config).get_device(&app, &device_id)?;
+    let credentials: Vec<Credential> = device
+        .spec
+        .credentials
+        .map(|c| c.credentials.iter().map(mask).collect())
+        .unwrap_or_default();
+
+    match output {
+        OutputFormat::Default => credentials_table(&credentials),
+        OutputFormat::Json => util::show_json(serde_json::to_string(&credentials)?),
+        OutputFormat::Yaml => print!("{}", serde_yaml::to_string(&credentials)?),
+        OutputFormat::JsonPath(path) => {
+            outputs::print_jsonpath(&outputs::to_values(&credentials)?, &path)?
+        }
+        OutputFormat::Template(template) => {
+            outputs::print_template(&outputs::to_values(&credentials)?, &template)?
+        }
+        // credentials have no name, and nothing more to show.
+        OutputFormat::Name | OutputFormat::Wide => {
+            return Err(anyhow!(
+                "Credentials can't be shown with the name and wide outputs."
+            ))
+        }
     }
-    }});
+    Ok(())
+}
 
-    set(config, app, device_id, data)
+/// Add a credential to the device, unless it already has it.
+pub fn add_credential(
+    config: &Context,
+    app: AppId,
+    device_id: DeviceId,
+    credential: Credential,
+) -> Result<()> {
+    set(config, app, device_id, |device| {
+        let credentials = &mut device
+            .spec
+            .credentials
+            .get_or_insert_with(Default::default)
+            .credentials;
+        if !credentials.contains(&credential) {
+            credentials.push(credential.clone());
+        }
+        Ok(())
+    })
 }
 
-pub fn set_password(
+pub fn remove_credentials(
     config: &Context,
     app: AppId,
     device_id: DeviceId,
-    password: String,
-    username: Option<&str>,
+    selector: CredentialSelector,
 ) -> Result<()> {
-    let credential = match username {
-        Some(user) => json!({"user": {"username": user, "password": password}}),
-        None => json!({ "pass": password }),
+    // The index is resolved once: when retrying after a conflict, the same credential is removed
+    // even if the list was reordered in the meantime.
+    let selected: Box<dyn Fn(&Credential) -> bool> = match selector {
+        CredentialSelector::Index(index) => {
+            let device = RegistryClient::new(config).get_device(&app, &device_id)?;
+            let credential = device
+                .spec
+                .credentials
+                .and_then(|c| c.credentials.into_iter().nth(index))
+                .ok_or_else(|| {
+                    anyhow!("Device {} has no credential at index {}", device_id, index)
+                })?;
+            Box::new(move |c| *c == credential)
+        }
+        CredentialSelector::Type(type_name) => Box::new(move |c| c.type_name() == type_name),
     };
 
-    // prepare json data to merge
-    let data = json!({"spec": {
-    "credentials": {
-        "credentials": [
-          credential
-        ]
-    }
-    }});
-
-    set(config, app, device_id, data)
-}
-
-// The "set" operation merges the data with what already exists on the server side
-fn set(config: &Context, app: AppId, device_id: DeviceId, data: Value) -> Result<()> {
-    //read device data
-    let res = get(&config, &app, &device_id);
-    match res {
-        Ok(r) => match r.status() {
-            StatusCode::OK => {
-                let mut body: Value =
-                    serde_json::from_str(r.text().unwrap_or_else(|_| "{}".to_string()).as_str())?;
-                body.merge(data);
-                put(&config, &app, &device_id, body)
-                    .map(|p| util::print_result(p, format!("Device {}", device_id), Verbs::edit))
-            }
-            e => {
-                log::error!("Error : could not retrieve device: {}", e);
-                util::exit_with_code(e)
+    set(config, app, device_id.clone(), |device| {
+        let credentials = &mut device
+            .spec
+            .credentials
+            .get_or_insert_with(Default::default)
+            .credentials;
+        let count = credentials.len();
+        credentials.retain(|c| !selected(c));
+
+        if credentials.len() == count {
+            Err(anyhow!(
+                "No matching credential found for device {}",
+                device_id
+            ))
+        } else {
+            Ok(())
+        }
+    })
+}
+
+// The "set" operation applies a change to what already exists on the server side.
+// If the device is modified concurrently, the change is applied again on the latest version.
+fn set<F>(config: &Context, app: AppId, device_id: DeviceId, change: F) -> Result<()>
+where
+    F: Fn(&mut Device) -> Result<()>,
+{
+    let client = RegistryClient::new(config);
+    let mut attempt = 1;
+
+    loop {
+        let mut device = client.get_device(&app, &device_id)?;
+        change(&mut device)?;
+
+        match client.update_device(&app, &device_id, &device) {
+            Ok(()) => break,
+            Err(e) if e.is_conflict() && attempt < SET_ATTEMPTS => {
+                log::warn!("Device {} was modified concurrently, retrying.", device_id);
+                attempt += 1;
             }
-        },
-        Err(e) => {
-            log::error!("Error : could not execute request: {}", e);
-            exit(2)
+            Err(e) => return Err(e.into()),
         }
     }
+
+    println!("Device {} updated.", device_id);
+    Ok(())
 }
 
-fn get(config: &Context, app: &str, device_id: &DeviceId) -> Result<Response> {
-    let client = Client::new();
-    let url = craft_url(&config.registry_url, app, Some(&device_id));
+fn pretty_list(devices: &[Device], wide: bool) -> Result<()> {
+    let mut table = if wide {
+        let mut table = Table::new("{:<} {:<} {:<} {:<} {:<} {:<}");
+        table.add_row(
+            Row::new()
+                .with_cell("NAME")
+                .with_cell("AGE")
+                .with_cell("LABELS")
+                .with_cell("GATEWAYS")
+                .with_cell("CREDENTIALS")
+                .with_cell("STATUS"),
+        );
+        table
+    } else {
+        let mut table = Table::new("{:<} {:<}");
+        table.add_row(Row::new().with_cell("NAME").with_cell("AGE"));
+        table
+    };
+
+    for dev in devices {
+        let mut row = Row::new()
+            .with_cell(&dev.metadata.name)
+            .with_cell(util::age(&dev.metadata));
+        if wide {
+            row.add_cell(outputs::labels_column(&dev.metadata));
+            row.add_cell(gateways_column(dev));
+            row.add_cell(credentials_column(dev));
+            row.add_cell(outputs::conditions_column(&dev.status));
+        }
+        table.add_row(row);
+    }
 
-    client
-        .get(&url)
-        .bearer_auth(&config.token.access_token().secret())
-        .send()
-        .context("Can't get device.")
+    print!("{}", table);
+    Ok(())
 }
 
-fn put(
-    config: &Context,
-    app: &AppId,
-    device_id: &DeviceId,
-    data: serde_json::Value,
-) -> Result<Response> {
-    let client = Client::new();
-    let url = craft_url(&config.registry_url, app, Some(&device_id));
-    let token = &config.token.access_token().secret();
-
-    client
-        .put(&url)
-        .header(reqwest::header::CONTENT_TYPE, "application/json")
-        .bearer_auth(token)
-        .body(data.to_string())
-        .send()
-        .context(format!(
-            "Error while updating device data for {}",
-            device_id
-        ))
-}
-
-// todo drogue-client and the types would be useful for this
-fn pretty_list(data: String) -> Result<()> {
-    let device_array: Vec<Value> = from_str(data.as_str())?;
-
-    let mut table = Table::new("{:<} {:<}");
-    table.add_row(Row::new().with_cell("NAME").with_cell("AGE"));
-
-    for dev in device_array {
-        let name = dev["metadata"]["name"].as_str();
-        let creation = dev["metadata"]["creationTimestamp"].as_str();
-        if let Some(name) = name {
-            table.add_row(
-                Row::new()
-                    .with_cell(name)
-                    .with_cell(util::age(creation.unwrap())?),
-            );
+fn gateways_column(dev: &Device) -> String {
+    dev.spec
+        .gateway_selector
+        .as_ref()
+        .map(|g| g.match_names.join(","))
+        .and_then(outputs::none_if_empty)
+        .unwrap_or_else(|| "<none>".to_string())
+}
+
+// only the credential types are shown, never the secrets.
+fn credentials_column(dev: &Device) -> String {
+    dev.spec
+        .credentials
+        .as_ref()
+        .map(|c| {
+            c.credentials
+                .iter()
+                .map(Credential::type_name)
+                .collect::<Vec<&str>>()
+                .join(",")
+        })
+        .and_then(outputs::none_if_empty)
+        .unwrap_or_else(|| "<none>".to_string())
+}
+
+// never show the secrets.
+fn mask(credential: &Credential) -> Credential {
+    match credential {
+        Credential::UsernamePassword {
+            username,
+            password,
+            unique,
+        } => Credential::UsernamePassword {
+            username: username.clone(),
+            password: mask_password(password),
+            unique: *unique,
+        },
+        Credential::Password(password) => Credential::Password(mask_password(password)),
+        Credential::Certificate(alias) => Credential::Certificate(alias.clone()),
+        Credential::PreSharedKey(psk) => Credential::PreSharedKey(PreSharedKey {
+            key: MASK.to_string(),
+            ..psk.clone()
+        }),
+        // nothing tells what is secret in an unknown credential.
+        Credential::Other(other) => Credential::Other(
+            other
+                .keys()
+                .map(|k| (k.clone(), Value::String(MASK.to_string())))
+                .collect(),
+        ),
+    }
+}
+
+// the kind of hash is kept.
+fn mask_password(password: &Password) -> Password {
+    match password {
+        Password::Plain(_) => Password::Plain(MASK.to_string()),
+        Password::BCrypt(_) => Password::BCrypt(MASK.to_string()),
+        Password::Sha512(_) => Password::Sha512(MASK.to_string()),
+    }
+}
+
+fn credentials_table(credentials: &[Credential]) {
+    let mut table = Table::new("{:<} {:<} {:<}");
+    table.add_row(
+        Row::new()
+            .with_cell("INDEX")
+            .with_cell("TYPE")
+            .with_cell("DETAILS"),
+    );
+
+    for (index, credential) in credentials.iter().enumerate() {
+        let details = match credential {
+            Credential::UsernamePassword {
+                username,
+                password,
+                unique,
+            } => {
+                let details = format!("username={}, hash={}", username, password.hash_name());
+                if *unique {
+                    format!("{}, unique", details)
+                } else {
+                    details
+                }
+            }
+            Credential::Password(password) => format!("hash={}", password.hash_name()),
+            Credential::Certificate(alias) => format!("alias={}", alias),
+            Credential::PreSharedKey(_) | Credential::Other(_) => "<none>".to_string(),
+        };
+        table.add_row(
+            Row::new()
+                .with_cell(index)
+                .with_cell(credential.type_name())
+                .with_cell(details),
+        );
+    }
+
+    print!("{}", table);
+}
+
+// The devices behind each gateway, from the gateway selectors of the devices.
+fn served_devices(devices: &[Device]) -> BTreeMap<DeviceId, Vec<DeviceId>> {
+    let mut gateways: BTreeMap<DeviceId, Vec<DeviceId>> = BTreeMap::new();
+    for device in devices {
+        let selector = device.spec.gateway_selector.as_ref();
+        for gateway in selector.iter().flat_map(|s| &s.match_names) {
+            gateways
+                .entry(gateway.clone())
+                .or_default()
+                .push(device.metadata.name.clone());
         }
     }
+    gateways
+}
+
+fn gateways_table(gateways: &[Gateway], wide: bool) {
+    let mut table = if wide {
+        let mut table = Table::new("{:<} {:<} {:<}");
+        table.add_row(
+            Row::new()
+                .with_cell("NAME")
+                .with_cell("DEVICES")
+                .with_cell("DEVICE NAMES"),
+        );
+        table
+    } else {
+        let mut table = Table::new("{:<} {:<}");
+        table.add_row(Row::new().with_cell("NAME").with_cell("DEVICES"));
+        table
+    };
+
+    for gateway in gateways {
+        let mut row = Row::new()
+            .with_cell(&gateway.name)
+            .with_cell(gateway.devices.len());
+        if wide {
+            row.add_cell(gateway.devices.join(","));
+        }
+        table.add_row(row);
+    }
 
     print!("{}", table);
-    Ok(())
+}
+
+// Gateways can be behind other gateways: the tree starts from the gateways which are not,
+// then the gateways left in a cycle.
+fn gateways_tree(gateways: &BTreeMap<DeviceId, Vec<DeviceId>>, existing: &HashSet<&str>) {
+    let served: HashSet<&str> = gateways.values().flatten().map(|d| d.as_str()).collect();
+    let roots = gateways.keys().filter(|g| !served.contains(g.as_str()));
+    let mut visited = HashSet::new();
+
+    for root in roots.chain(gateways.keys()) {
+        if visited.contains(root.as_str()) {
+            continue;
+        }
+        if existing.contains(root.as_str()) {
+            println!("{}", root);
+        } else {
+            println!("{} (not found)", root);
+        }
+        gateway_branches(gateways, root, "", &mut vec![], &mut visited);
+    }
+}
+
+fn gateway_branches<'a>(
+    gateways: &'a BTreeMap<DeviceId, Vec<DeviceId>>,
+    gateway: &'a str,
+    prefix: &str,
+    path: &mut Vec<&'a str>,
+    visited: &mut HashSet<&'a str>,
+) {
+    visited.insert(gateway);
+    path.push(gateway);
+
+    let devices = gateways.get(gateway).map(Vec::as_slice).unwrap_or_default();
+    for (i, device) in devices.iter().enumerate() {
+        let (branch, indent) = if i + 1 == devices.len() {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+        if path.contains(&device.as_str()) {
+            println!("{}{}{} (cycle)", prefix, branch, device);
+        } else {
+            println!("{}{}{}", prefix, branch, device);
+            let prefix = format!("{}{}", prefix, indent);
+            gateway_branches(gateways, device, &prefix, path, visited);
+        }
+    }
+
+    path.pop();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashed_passwords_verify() -> Result<()> {
+        match hash_password("secret", Some("bcrypt"))? {
+            Password::BCrypt(hash) => {
+                assert!(pwhash::bcrypt::verify("secret", &hash));
+                assert!(!pwhash::bcrypt::verify("other", &hash));
+            }
+            other => panic!("not a bcrypt hash: {:?}", other),
+        }
+        match hash_password("secret", Some("sha512"))? {
+            Password::Sha512(hash) => {
+                assert!(hash.starts_with("$6$"));
+                assert!(pwhash::sha512_crypt::verify("secret", &hash));
+                assert!(!pwhash::sha512_crypt::verify("other", &hash));
+            }
+            other => panic!("not a sha512 hash: {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn plain_and_unsupported_hashes() -> Result<()> {
+        assert_eq!(
+            hash_password("secret", None)?,
+            Password::Plain("secret".to_string())
+        );
+        assert!(hash_password("secret", Some("md5")).is_err());
+        Ok(())
+    }
 }
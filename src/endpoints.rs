@@ -0,0 +1,107 @@
+use anyhow::{Context as AnyhowContext, Result};
+use reqwest::blocking::Client;
+use serde_json::Value;
+use url::Url;
+
+pub fn url_validation(url: &str) -> Result<Url> {
+    Url::parse(url).or_else(|_| {
+        Url::parse(&format!("https://{}", url))
+            .context(format!("URL args: \'{}\' is not valid", url))
+    })
+}
+
+// use drogue's well known endpoint to retrieve endpoints.
+pub fn get_drogue_services_endpoint(url: Url) -> Result<(Url, Url)> {
+    let client = Client::new();
+
+    let url = url.join(".well-known/drogue-endpoints")?;
+
+    let res = client
+        .get(url)
+        .send()
+        .context("Can't retrieve drogue endpoints details")?;
+
+    let endpoints: Value = res
+        .json()
+        .context("Cannot deserialize drogue endpoints details")?;
+
+    let sso = endpoints["issuer_url"]
+        .as_str()
+        .context("Missing `issuer_url` in drogue endpoint details")?;
+    let registry = endpoints["registry"]["url"]
+        .as_str()
+        .context("Missing `registry` in drogue endpoint details")?;
+
+    // a trailing / is needed to append the rest of the path.
+    Ok((
+        url_validation(format!("{}/", sso).as_str())?,
+        url_validation(format!("{}/", registry).as_str())?,
+    ))
+}
+
+/// The endpoints of the openid-connect server.
+#[derive(Debug, Clone)]
+pub struct OpenIdEndpoints {
+    pub auth_url: Url,
+    pub token_url: Url,
+    /// Only advertised by servers supporting the device authorization grant.
+    pub device_authorization_url: Option<Url>,
+    /// Token revocation endpoint, see RFC 7009.
+    pub revocation_url: Option<Url>,
+    /// RP-initiated logout endpoint.
+    pub end_session_url: Option<Url>,
+}
+
+// use keycloak's well known endpoint to retrieve endpoints.
+// http://keycloakhost:keycloakport/auth/realms/{realm}/.well-known/openid-configuration
+pub fn get_openid_endpoints(issuer_url: Url) -> Result<OpenIdEndpoints> {
+    let client = Client::new();
+
+    let url = issuer_url.join(".well-known/openid-configuration")?;
+    let res = client
+        .get(url)
+        .send()
+        .context("Can't retrieve openid-connect endpoints details")?;
+
+    let endpoints: Value = res
+        .json()
+        .context("Cannot deserialize openid-connect endpoints details")?;
+
+    let auth = endpoints["authorization_endpoint"]
+        .as_str()
+        .context("Missing `authorization_endpoint` in drogue openid-connect configuration")?;
+    let token = endpoints["token_endpoint"]
+        .as_str()
+        .context("Missing `token_endpoint` in drogue openid-connect configuration")?;
+    let optional_url = |name: &str| endpoints[name].as_str().map(url_validation).transpose();
+
+    Ok(OpenIdEndpoints {
+        auth_url: url_validation(auth)?,
+        token_url: url_validation(token)?,
+        device_authorization_url: optional_url("device_authorization_endpoint")?,
+        revocation_url: optional_url("revocation_endpoint")?,
+        end_session_url: optional_url("end_session_endpoint")?,
+    })
+}
+
+// use drogue's well known endpoint to retrieve version.
+pub fn get_drogue_services_version(url: &Url) -> Result<String> {
+    let client = Client::new();
+
+    let url = url.join(".well-known/drogue-version")?;
+
+    let res = client
+        .get(url)
+        .send()
+        .context("Can't retrieve drogue version")?;
+
+    let payload: Value = res
+        .json()
+        .context("Cannot deserialize drogue version payload")?;
+
+    let version = payload["version"]
+        .as_str()
+        .context("Missing `version` in drogue version payload")?;
+
+    Ok(version.to_string())
+}
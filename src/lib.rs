@@ -0,0 +1,33 @@
+//! The reusable parts of drg: configuration contexts, authentication against drogue-cloud
+//! and a client for the device registry.
+//!
+//! ```no_run
+//! use drg::client::RegistryClient;
+//! use drg::config::Config;
+//!
+//! // other drg processes may refresh the token at the same time.
+//! let lock = Config::lock(None)?;
+//! let mut config = Config::from(None)?;
+//! if drg::openid::verify_token_validity(config.get_context_mut(&None)?)? {
+//!     config.write(None)?;
+//! }
+//! drop(lock);
+//!
+//! let context = config.get_context(&None)?;
+//!
+//! let client = RegistryClient::new(context);
+//! for app in client.list_apps(None)? {
+//!     println!("{}", app.metadata.name);
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+pub mod client;
+pub mod config;
+mod credentials;
+pub mod endpoints;
+pub mod models;
+pub mod openid;
+
+pub type AppId = String;
+pub type DeviceId = String;
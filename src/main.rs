@@ -1,66 +1,91 @@
+mod apply;
 mod apps;
 mod arguments;
-mod config;
 mod devices;
-mod openid;
+mod outputs;
+mod tokens;
 mod trust;
 mod util;
 
+use drg::{client, config, endpoints, models, openid, AppId, DeviceId};
+
 use arguments::{
     Context_subcommands, Other_commands, Other_flags, Parameters, Resources, Set_args, Set_targets,
-    Trust_subcommands, Verbs,
+    Token_subcommands, Trust_subcommands, Unset_targets, Verbs,
 };
 
-use crate::config::{Config, ContextId};
+use crate::config::{Config, Context, ContextId};
+use crate::openid::LogoutOutcome;
+use crate::outputs::OutputFormat;
 use anyhow::{anyhow, Context as AnyhowContext, Result};
+use clap::ArgMatches;
 use std::process::exit;
 use std::str::FromStr;
+use std::time::Duration;
 
-type AppId = String;
-type DeviceId = String;
-
-fn main() -> Result<()> {
+fn main() {
     let matches = arguments::parse_arguments();
-    let config_path = matches.value_of(Parameters::config);
-    let (command, submatches) = matches.subcommand();
-    let context_arg = matches.value_of(Parameters::context).map(|s| s.to_string());
 
     simple_logger::SimpleLogger::new()
         .with_level(util::log_level(&matches))
         .init()
         .unwrap();
 
-    // load the config file
-    let config_result: Result<Config> =
-        Config::from(config_path).context("Error loading config file");
+    if let Err(e) = run(&matches) {
+        // the value was validated by clap already.
+        let output = arguments::get_output_format(&matches).unwrap_or(OutputFormat::Default);
+        util::exit_with_error(e, &output);
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<()> {
+    let config_path = matches.value_of(Parameters::config);
+    let (command, submatches) = matches.subcommand();
+    let context_arg = matches.value_of(Parameters::context).map(|s| s.to_string());
 
     if command == Other_commands::login.as_ref() {
-        let url = util::url_validation(submatches.unwrap().value_of(Parameters::url).unwrap())?;
-        let refresh_token_val = submatches.unwrap().value_of(Other_commands::token);
+        let url =
+            endpoints::url_validation(submatches.unwrap().value_of(Parameters::url).unwrap())?;
+        let method = arguments::get_login_method(submatches.unwrap())?;
 
-        let mut config = config_result.unwrap_or_else(|_| Config::empty());
         let context = openid::login(
             url.clone(),
-            refresh_token_val,
+            method,
+            arguments::get_oauth_client(submatches.unwrap()),
             context_arg.unwrap_or("default".to_string() as ContextId),
+            &util::print_login_prompt,
         )?;
 
+        // the config file is only locked once the user logged in.
+        let _lock = Config::lock(config_path)?;
+        // don't replace a config file that can't be read, e.g. with a wrong passphrase.
+        let mut config = if Config::exists(config_path)? {
+            Config::from(config_path).context("Error loading config file")?
+        } else {
+            Config::empty()
+        };
+
         println!("\nSuccessfully authenticated to drogue cloud : {}", url);
         let name = context.name.clone();
-        config.add_context(context)?;
+        if config.add_context(context)? {
+            println!("Updated existing context {}", name);
+        }
 
         if !submatches.unwrap().is_present(Parameters::keep_current) {
-            config.set_active_context(name)?;
+            config.set_active_context(name.clone())?;
+            println!("Switched active context to: {}", name);
         }
 
         config.write(config_path)?;
         exit(0);
     } else if command == Other_commands::version.as_ref() {
-        util::print_version(&config_result);
+        util::print_version(&Config::from(config_path).context("Error loading config file"));
         exit(0);
     }
 
-    let mut config: Config = config_result?;
+    // Hold the lock from reading the config file until the changes or the refreshed token are written.
+    let lock = Config::lock(config_path)?;
+    let mut config = Config::from(config_path).context("Error loading config file")?;
 
     if command == Other_commands::context.as_ref() {
         let cmd = submatches.unwrap();
@@ -77,14 +102,17 @@ fn main() -> Result<()> {
                 println!("To create a new context use drg login");
             }
             Context_subcommands::list => {
-                config.list_contexts();
+                util::print_contexts(&config);
             }
             Context_subcommands::show => {
-                config.show()?;
+                let reveal = c.unwrap().is_present(Parameters::show_secrets);
+                println!("{}", config.to_yaml(reveal)?);
             }
             Context_subcommands::set_active => {
-                config.set_active_context(ctx_id.unwrap())?;
+                let name = ctx_id.unwrap();
+                config.set_active_context(name.clone())?;
                 config.write(config_path)?;
+                println!("Switched active context to: {}", name);
             }
             Context_subcommands::delete => {
                 let id = ctx_id.unwrap();
@@ -98,6 +126,16 @@ fn main() -> Result<()> {
                 context.set_default_app(id);
                 config.write(config_path)?;
             }
+            Context_subcommands::encrypt => {
+                config.encrypt_credentials()?;
+                config.write(config_path)?;
+                println!("The credentials are encrypted.");
+            }
+            Context_subcommands::decrypt => {
+                config.decrypt_credentials();
+                config.write(config_path)?;
+                println!("The credentials are no longer encrypted.");
+            }
             Context_subcommands::rename => {
                 let new_ctx = c.unwrap().value_of("new_context_id").unwrap().to_string();
 
@@ -108,19 +146,51 @@ fn main() -> Result<()> {
         exit(0);
     }
 
+    if command == Other_commands::logout.as_ref() {
+        let submatches = submatches.unwrap();
+        let ctx_id = submatches
+            .value_of(Parameters::context_name)
+            .map(|s| s.to_string())
+            .or(context_arg);
+
+        let context = config.get_context_mut(&ctx_id)?;
+        let name = context.name.clone();
+        match openid::logout(context)? {
+            LogoutOutcome::Revoked => {}
+            LogoutOutcome::NotRevoked => println!(
+                "The authentication server does not support revoking tokens, the session stays valid until it expires."
+            ),
+            LogoutOutcome::AccessTokenRemoved => println!(
+                "The access token is only removed from the context, use drg token delete to revoke it."
+            ),
+            LogoutOutcome::NotLoggedIn => log::info!("Context {} is not logged in.", name),
+        }
+
+        if submatches.is_present(Parameters::remove) {
+            config.delete_context(&name)?;
+        }
+        config.write(config_path)?;
+        println!("Logged out of context {}.", name);
+        exit(0);
+    }
+
     // The following commands needs a context and a valid token
     if openid::verify_token_validity(config.get_context_mut(&context_arg)?)? {
         config.write(config_path)?;
     }
+    drop(lock);
     let context = config.get_context(&context_arg)?;
 
     if command == Other_commands::whoami.as_ref() {
         let (_, submatches) = matches.subcommand();
         if submatches.unwrap().is_present("token") {
-            openid::print_token(&context);
+            util::print_token(context);
         } else {
-            openid::print_whoami(&context);
-            util::print_version(&Ok(config));
+            let output = arguments::get_output_format(matches)?;
+            util::print_whoami(context, &output)?;
+            if output != OutputFormat::Json {
+                util::print_version(&Ok(config));
+            }
         }
         exit(0)
     }
@@ -128,13 +198,13 @@ fn main() -> Result<()> {
     if command == Other_commands::trust.as_ref() {
         let (v, command) = submatches.unwrap().subcommand();
         let verb = Trust_subcommands::from_str(v);
-        let app_id = arguments::get_app_id(&command.unwrap(), &context)?;
+        let app_id = arguments::get_app_id(command.unwrap(), context)?;
         let days = command.unwrap().value_of(&Parameters::days);
 
         match verb? {
             Trust_subcommands::create => {
                 let keyout = command.unwrap().value_of(&Parameters::key_output);
-                apps::add_trust_anchor(&context, &app_id, keyout, days)
+                apps::add_trust_anchor(context, &app_id, keyout, days)
             }
             Trust_subcommands::add => {
                 let ca_key = &command
@@ -153,11 +223,11 @@ fn main() -> Result<()> {
 
                 let device_key = command.unwrap().value_of(&Parameters::key_output);
 
-                let cert = apps::get_trust_anchor(&context, &app_id)?;
+                let cert = apps::get_trust_anchor(context, &app_id)?;
 
                 trust::create_device_certificate(
                     &app_id,
-                    &device_id,
+                    device_id,
                     ca_key,
                     &cert,
                     device_key,
@@ -169,11 +239,39 @@ fn main() -> Result<()> {
         exit(0);
     }
 
+    if command == Other_commands::token.as_ref() {
+        let (v, command) = submatches.unwrap().subcommand();
+        let output = arguments::get_output_format(matches)?;
+
+        match Token_subcommands::from_str(v)? {
+            Token_subcommands::create => {
+                let description = command.unwrap().value_of(Parameters::description);
+                tokens::create(context, description, output)
+            }
+            Token_subcommands::list => tokens::list(context, output),
+            Token_subcommands::delete => {
+                let prefix = command.unwrap().value_of(Parameters::prefix).unwrap();
+                tokens::delete(context, prefix)
+            }
+        }?;
+        exit(0);
+    }
+
     log::warn!("Using context: {}", context.name);
     let verb = Verbs::from_str(command);
     let cmd = submatches.unwrap();
 
     match verb? {
+        Verbs::apply => {
+            let files = cmd.values_of(Parameters::filename).unwrap().collect();
+            apply::apply(context, files)?;
+        }
+        Verbs::diff => {
+            let files = cmd.values_of(Parameters::filename).unwrap().collect();
+            if apply::diff(context, files)? {
+                exit(util::EXIT_DIFFERENCES_FOUND);
+            }
+        }
         Verbs::create => {
             let (res, command) = cmd.subcommand();
             let data = util::json_parse(command.unwrap().value_of(Parameters::spec))?;
@@ -187,9 +285,9 @@ fn main() -> Result<()> {
             let file = command.unwrap().value_of(Parameters::filename);
 
             match resource? {
-                Resources::app => apps::create(&context, id, data, file),
+                Resources::app => apps::create(context, id, data, file),
                 Resources::device => {
-                    let app_id = arguments::get_app_id(&command.unwrap(), &context)?;
+                    let app_id = arguments::get_app_id(command.unwrap(), context)?;
 
                     let id = if command.unwrap().is_present(&Other_flags::cert) {
                         format!("CN={}, O=Drogue IoT, OU={}", id, app_id)
@@ -197,7 +295,12 @@ fn main() -> Result<()> {
                         id
                     };
 
-                    devices::create(&context, id, data, app_id, file)
+                    devices::create(context, id, data, app_id, file)
+                }
+                Resources::credentials => {
+                    let app_id = arguments::get_app_id(command.unwrap(), context)?;
+                    let credential = arguments::get_credential(command.unwrap())?;
+                    devices::add_credential(context, app_id, id as DeviceId, credential)
                 }
                 // ignore apps and devices keywords
                 _ => Err(anyhow!("Cannot create multiple resources")),
@@ -213,10 +316,15 @@ fn main() -> Result<()> {
             let resource = Resources::from_str(res);
 
             match resource? {
-                Resources::app => apps::delete(&context, id),
+                Resources::app => apps::delete(context, id),
                 Resources::device => {
-                    let app_id = arguments::get_app_id(&command.unwrap(), &context)?;
-                    devices::delete(&context, app_id, id)
+                    let app_id = arguments::get_app_id(command.unwrap(), context)?;
+                    devices::delete(context, app_id, id)
+                }
+                Resources::credentials => {
+                    let app_id = arguments::get_app_id(command.unwrap(), context)?;
+                    let selector = arguments::get_credential_selector(command.unwrap())?;
+                    devices::remove_credentials(context, app_id, id as DeviceId, selector)
                 }
                 // ignore apps and devices keywords
                 _ => Err(anyhow!("Cannot delete multiple resources")),
@@ -233,10 +341,10 @@ fn main() -> Result<()> {
             let resource = Resources::from_str(res);
 
             match resource? {
-                Resources::app => apps::edit(&context, id, file),
+                Resources::app => apps::edit(context, id, file),
                 Resources::device => {
-                    let app_id = arguments::get_app_id(&command.unwrap(), &context)?;
-                    devices::edit(&context, app_id, id, file)
+                    let app_id = arguments::get_app_id(command.unwrap(), context)?;
+                    devices::edit(context, app_id, id, file)
                 }
                 // ignore apps and devices keywords
                 _ => Err(anyhow!("Cannot edit multiple resources")),
@@ -244,6 +352,7 @@ fn main() -> Result<()> {
         }
         Verbs::get => {
             let (res, command) = cmd.subcommand();
+            let output = arguments::get_output_format(matches)?;
 
             let resource = Resources::from_str(res)?;
 
@@ -257,37 +366,107 @@ fn main() -> Result<()> {
                 .values_of(Parameters::labels)
                 .map(|v| v.collect::<Vec<&str>>().join(","));
 
+            // a watch outlives the token, the refreshed ones are saved for the next commands.
+            let save_token = |context: &Context| config.save_token(config_path, context);
+            let watch = if command.unwrap().is_present(Parameters::watch) {
+                let seconds = command
+                    .unwrap()
+                    .value_of(Parameters::interval)
+                    .unwrap_or("5");
+                Some(Duration::from_secs(seconds.parse()?))
+            } else {
+                None
+            };
+
             match resource {
                 Resources::app | Resources::apps => {
-                    match id {
-                        Some(id) => apps::read(&context, id as AppId),
-                        None => apps::list(&context, labels),
+                    match (id, watch) {
+                        (Some(id), None) => apps::read(context, id as AppId, output),
+                        (None, None) => apps::list(context, labels, output),
+                        (None, Some(interval)) => {
+                            apps::watch(context, labels, &save_token, output, interval)
+                        }
+                        (Some(_), Some(_)) => Err(anyhow!("Cannot watch a single resource")),
                     }?;
                 }
                 Resources::device | Resources::devices => {
-                    let app_id = arguments::get_app_id(&command.unwrap(), &context)?;
-                    match id {
-                        Some(id) => devices::read(&context, app_id, id as DeviceId),
-                        None => devices::list(&context, app_id, labels),
+                    let app_id = arguments::get_app_id(command.unwrap(), context)?;
+                    match (id, watch) {
+                        (Some(id), None) => devices::read(context, app_id, id as DeviceId, output),
+                        (None, None) => devices::list(context, app_id, labels, output),
+                        (None, Some(interval)) => {
+                            devices::watch(context, app_id, labels, &save_token, output, interval)
+                        }
+                        (Some(_), Some(_)) => Err(anyhow!("Cannot watch a single resource")),
                     }?;
                 }
+                Resources::credentials => {
+                    let app_id = arguments::get_app_id(command.unwrap(), context)?;
+                    devices::read_credentials(context, app_id, id.unwrap() as DeviceId, output)?;
+                }
+                Resources::gateways => {
+                    let app_id = arguments::get_app_id(command.unwrap(), context)?;
+                    let tree = command.unwrap().is_present(Parameters::tree);
+                    devices::list_gateways(context, app_id, tree, output)?;
+                }
             }
         }
         Verbs::set => {
             let (res, command) = cmd.subcommand();
-            let args: Vec<&str> = command.unwrap().values_of(Verbs::set).unwrap().collect();
+            let command = command.unwrap();
+            let args: Vec<&str> = command.values_of(Verbs::set).unwrap().collect();
 
-            // clap already makes sure vals contains two values
-            let (device, value) = (args[0].to_string(), args[1].to_string());
-            let app_id = arguments::get_app_id(&command.unwrap(), &context)?;
+            // clap already makes sure there is a device, and a value unless it is optional.
+            let (device, value) = (args[0].to_string(), args.get(1).map(|v| v.to_string()));
+            let app_id = arguments::get_app_id(command, context)?;
 
             match Set_targets::from_str(res)? {
                 Set_targets::gateway => {
-                    devices::set_gateway(&context, app_id, device as DeviceId, value)?;
+                    let gateways = args[1..].iter().map(|g| g.to_string()).collect();
+                    let update = if command.is_present(Parameters::remove) {
+                        devices::GatewayUpdate::Remove
+                    } else if command.is_present(Parameters::replace) {
+                        devices::GatewayUpdate::Replace
+                    } else {
+                        devices::GatewayUpdate::Add
+                    };
+                    devices::set_gateways(context, app_id, device as DeviceId, gateways, update)?;
                 }
                 Set_targets::password => {
-                    let username = command.unwrap().value_of(Set_args::username);
-                    devices::set_password(&context, app_id, device as DeviceId, value, username)?;
+                    let given = arguments::get_set_value(command, value, "password")?;
+                    let generate = given.is_none();
+                    let plain = given.unwrap_or_else(devices::generate_password);
+                    let password =
+                        devices::hash_password(&plain, command.value_of(Parameters::hash))?;
+                    let username = command.value_of(Set_args::username);
+
+                    devices::set_password(context, app_id, device.clone(), password, username)?;
+                    if generate {
+                        let file = command.value_of(Parameters::out);
+                        devices::show_generated_password(&device, &plain, file)?;
+                    }
+                }
+                Set_targets::psk => {
+                    let key = match arguments::get_set_value(command, value, "key")? {
+                        Some(key) => devices::parse_psk(&key, command.is_present(Parameters::hex))?,
+                        None => {
+                            let length = command.value_of(Parameters::length).unwrap_or("32");
+                            devices::generate_psk(length.parse()?)
+                        }
+                    };
+                    devices::set_psk(context, app_id, device as DeviceId, key)?;
+                }
+            }
+        }
+        Verbs::unset => {
+            let (res, command) = cmd.subcommand();
+            let command = command.unwrap();
+            let device = command.value_of(Parameters::id).unwrap().to_string();
+            let app_id = arguments::get_app_id(command, context)?;
+
+            match Unset_targets::from_str(res)? {
+                Unset_targets::gateway => {
+                    devices::unset_gateways(context, app_id, device as DeviceId)?;
                 }
             }
         }
@@ -0,0 +1,349 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+// The registry objects of drogue-cloud.
+// Every struct keeps the fields it doesn't know about in `extra`, so they are
+// sent back untouched when the object is updated.
+
+pub trait Resource {
+    fn metadata(&self) -> &ObjectMeta;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ObjectMeta {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub application: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub creation_timestamp: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource_version: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Status {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<Condition>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Condition {
+    #[serde(rename = "type")]
+    pub condition_type: String,
+    pub status: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Application {
+    pub metadata: ObjectMeta,
+    #[serde(default)]
+    pub spec: ApplicationSpec,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplicationSpec {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trust_anchors: Option<TrustAnchors>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TrustAnchors {
+    #[serde(default)]
+    pub anchors: Vec<TrustAnchor>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TrustAnchor {
+    /// Base64 encoded PEM certificate.
+    pub certificate: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Device {
+    pub metadata: ObjectMeta,
+    #[serde(default)]
+    pub spec: DeviceSpec,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceSpec {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credentials: Option<Credentials>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gateway_selector: Option<GatewaySelector>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Credentials {
+    #[serde(default)]
+    pub credentials: Vec<Credential>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Credential {
+    #[serde(rename = "user")]
+    UsernamePassword {
+        username: String,
+        password: Password,
+        #[serde(default, skip_serializing_if = "is_false")]
+        unique: bool,
+    },
+    #[serde(rename = "pass")]
+    Password(Password),
+    #[serde(rename = "cert")]
+    Certificate(String),
+    #[serde(rename = "psk")]
+    PreSharedKey(PreSharedKey),
+    /// A credential type drg doesn't know, kept as is.
+    #[serde(untagged)]
+    Other(Map<String, Value>),
+}
+
+/// A password, in plain text or hashed. Plain passwords are serialized as a simple string.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "PasswordValue", into = "PasswordValue")]
+pub enum Password {
+    Plain(String),
+    /// A bcrypt hash, e.g. `$2b$10$...`
+    BCrypt(String),
+    /// A SHA-512 crypt hash with its salt, e.g. `$6$salt$...`
+    Sha512(String),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum PasswordValue {
+    Plain(String),
+    Tagged(TaggedPassword),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TaggedPassword {
+    Plain(String),
+    Bcrypt(String),
+    Sha512(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PreSharedKey {
+    /// Base64 encoded key.
+    pub key: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GatewaySelector {
+    #[serde(default)]
+    pub match_names: Vec<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// A personal access token, as listed by the token API. The secret is never returned.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AccessTokenInfo {
+    pub prefix: String,
+    pub created: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// A newly created access token, the only time its secret is available.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreatedAccessToken {
+    pub prefix: String,
+    pub token: String,
+}
+
+impl Application {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Application {
+            metadata: ObjectMeta {
+                name: name.into(),
+                ..Default::default()
+            },
+            spec: Default::default(),
+            status: None,
+            extra: Default::default(),
+        }
+    }
+}
+
+impl Device {
+    pub fn new<A: Into<String>, S: Into<String>>(application: A, name: S) -> Self {
+        Device {
+            metadata: ObjectMeta {
+                name: name.into(),
+                application: Some(application.into()),
+                ..Default::default()
+            },
+            spec: Default::default(),
+            status: None,
+            extra: Default::default(),
+        }
+    }
+}
+
+impl Resource for Application {
+    fn metadata(&self) -> &ObjectMeta {
+        &self.metadata
+    }
+}
+
+impl Resource for Device {
+    fn metadata(&self) -> &ObjectMeta {
+        &self.metadata
+    }
+}
+
+impl Credential {
+    /// The name of the credential type, as used in the device spec.
+    pub fn type_name(&self) -> &str {
+        match self {
+            Credential::UsernamePassword { .. } => "user",
+            Credential::Password(_) => "pass",
+            Credential::Certificate(_) => "cert",
+            Credential::PreSharedKey(_) => "psk",
+            Credential::Other(other) => other.keys().next().map_or("unknown", String::as_str),
+        }
+    }
+}
+
+impl Password {
+    /// The name of the hash, or `plain`.
+    pub fn hash_name(&self) -> &'static str {
+        match self {
+            Password::Plain(_) => "plain",
+            Password::BCrypt(_) => "bcrypt",
+            Password::Sha512(_) => "sha512",
+        }
+    }
+}
+
+impl From<PasswordValue> for Password {
+    fn from(value: PasswordValue) -> Self {
+        match value {
+            PasswordValue::Plain(p) | PasswordValue::Tagged(TaggedPassword::Plain(p)) => {
+                Password::Plain(p)
+            }
+            PasswordValue::Tagged(TaggedPassword::Bcrypt(p)) => Password::BCrypt(p),
+            PasswordValue::Tagged(TaggedPassword::Sha512(p)) => Password::Sha512(p),
+        }
+    }
+}
+
+impl From<Password> for PasswordValue {
+    fn from(password: Password) -> Self {
+        match password {
+            Password::Plain(p) => PasswordValue::Plain(p),
+            Password::BCrypt(p) => PasswordValue::Tagged(TaggedPassword::Bcrypt(p)),
+            Password::Sha512(p) => PasswordValue::Tagged(TaggedPassword::Sha512(p)),
+        }
+    }
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{from_value, json, to_value};
+
+    // the form the registry stores, and the password it is read as.
+    fn assert_round_trip(wire: Value, password: Password) {
+        assert_eq!(from_value::<Password>(wire.clone()).unwrap(), password);
+        assert_eq!(to_value(&password).unwrap(), wire);
+    }
+
+    #[test]
+    fn plain_password() {
+        assert_round_trip(json!("secret"), Password::Plain("secret".to_string()));
+    }
+
+    #[test]
+    fn bcrypt_password() {
+        assert_round_trip(
+            json!({"bcrypt": "$2b$10$hash"}),
+            Password::BCrypt("$2b$10$hash".to_string()),
+        );
+    }
+
+    #[test]
+    fn sha512_password() {
+        assert_round_trip(
+            json!({"sha512": "$6$salt$hash"}),
+            Password::Sha512("$6$salt$hash".to_string()),
+        );
+    }
+
+    #[test]
+    fn tagged_plain_password() {
+        // written as a simple string when sent back.
+        let password: Password = from_value(json!({"plain": "secret"})).unwrap();
+        assert_eq!(password, Password::Plain("secret".to_string()));
+        assert_eq!(to_value(&password).unwrap(), json!("secret"));
+    }
+
+    #[test]
+    fn password_credentials() {
+        let credentials = json!([
+            {"pass": {"bcrypt": "$2b$10$hash"}},
+            {"user": {"username": "foo", "password": "secret", "unique": true}},
+        ]);
+        let parsed: Vec<Credential> = from_value(credentials.clone()).unwrap();
+        assert_eq!(
+            parsed,
+            vec![
+                Credential::Password(Password::BCrypt("$2b$10$hash".to_string())),
+                Credential::UsernamePassword {
+                    username: "foo".to_string(),
+                    password: Password::Plain("secret".to_string()),
+                    unique: true,
+                },
+            ]
+        );
+        assert_eq!(to_value(&parsed).unwrap(), credentials);
+    }
+}
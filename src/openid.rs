@@ -1,42 +1,167 @@
 use oauth2::basic::{BasicClient, BasicTokenResponse};
 use oauth2::reqwest::http_client;
 use oauth2::{
-    AuthUrl, AuthorizationCode, ClientId, CsrfToken, PkceCodeChallenge, RedirectUrl, Scope,
-    TokenResponse, TokenUrl,
+    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge, RedirectUrl,
+    Scope, TokenResponse, TokenUrl,
 };
 
 use anyhow::Error;
 use anyhow::Result;
+use anyhow::{anyhow, Context as AnyhowContext};
 
-use tiny_http::{Response, Server};
+use tiny_http::{Header, Response, Server};
 
 use qstring::QString;
+use reqwest::blocking::RequestBuilder;
 use reqwest::Url;
 
-use crate::config::{self, Context};
-use crate::util;
+use crate::client::TokenClient;
+use crate::config::{self, AccessToken, ClientCredentials, Context, OAuthClient, Token};
+use crate::endpoints;
 use chrono::{DateTime, Duration, Utc};
-use std::net::{Ipv4Addr, SocketAddr};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::thread::sleep;
+use std::time::{Duration as StdDuration, Instant};
+
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+// How long to wait for the user to log in with the browser.
+const LOGIN_TIMEOUT: StdDuration = StdDuration::from_secs(300);
+
+/// How the user authenticates with `login`.
+pub enum LoginMethod<'a> {
+    /// Authorization code flow, through a browser.
+    Browser(RedirectAddress),
+    /// Exchange an existing refresh token.
+    RefreshToken(&'a str),
+    /// Device authorization grant, for hosts without a browser.
+    DeviceCode,
+    /// Client credentials grant, for service accounts.
+    ClientCredentials(ClientCredentials),
+    /// A drogue-cloud access token, no openid-connect token is needed.
+    AccessToken(AccessToken),
+}
+
+/// What `logout` did with the token of the context.
+#[derive(Debug, PartialEq)]
+pub enum LogoutOutcome {
+    /// The session was ended on the authentication server.
+    Revoked,
+    /// The authentication server can't revoke tokens, the session stays valid until it expires.
+    NotRevoked,
+    /// Access tokens are not known to the authentication server, it is only removed from the context.
+    AccessTokenRemoved,
+    /// The context had no token.
+    NotLoggedIn,
+}
+
+/// Where the browser is sent back to once the user logged in.
+/// The address must be registered as a redirect URI of the client.
+pub struct RedirectAddress {
+    pub host: String,
+    /// 0 picks a random port.
+    pub port: u16,
+}
 
-const CLIENT_ID: &str = "drogue";
+impl RedirectAddress {
+    // The address the local server listens on for the redirect.
+    fn bind_address(&self) -> Result<SocketAddr> {
+        // localhost may resolve to ::1 first, while browsers commonly use 127.0.0.1.
+        if self.host == "localhost" {
+            return Ok(SocketAddr::from((Ipv4Addr::LOCALHOST, self.port)));
+        }
+        (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .context(format!("Cannot resolve the redirect host {}", self.host))?
+            .next()
+            .ok_or_else(|| anyhow!("Cannot resolve the redirect host {}", self.host))
+    }
+}
 
+impl Default for RedirectAddress {
+    fn default() -> Self {
+        RedirectAddress {
+            host: "localhost".to_string(),
+            port: 0,
+        }
+    }
+}
+
+/// What the user has to do to complete an interactive login, for the caller to show.
+pub enum LoginPrompt<'a> {
+    /// The browser could not be opened, the user browses to the URL.
+    Browse(&'a Url),
+    /// The user browses to `verification_uri` on any device and enters `user_code`.
+    DeviceCode {
+        verification_uri: &'a str,
+        user_code: &'a str,
+        /// The same, with the code already filled in.
+        verification_uri_complete: Option<&'a str>,
+    },
+}
+
+/// Log in with the given method, `oauth_client` defaults to the drg client.
+/// `prompt` is called when the user has to act to complete the login.
 pub fn login(
     api_endpoint: Url,
-    refresh_token_val: Option<&str>,
+    method: LoginMethod,
+    oauth_client: Option<OAuthClient>,
     context_name: config::ContextId,
+    prompt: &dyn Fn(LoginPrompt),
 ) -> Result<Context> {
     log::info!("Starting authentication process with {}", api_endpoint);
 
-    let (sso_url, registry_url) = util::get_drogue_services_endpoint(api_endpoint.clone())?;
-    let (auth_url, token_url) = util::get_auth_and_tokens_endpoints(sso_url)?;
+    let (sso_url, registry_url) = endpoints::get_drogue_services_endpoint(api_endpoint.clone())?;
+    let endpoints = endpoints::get_openid_endpoints(sso_url)?;
+    let (auth_url, token_url) = (endpoints.auth_url.clone(), endpoints.token_url.clone());
+    let client = oauth_client.clone().unwrap_or_default();
 
-    let token = match refresh_token_val {
-        Some(refresh_token_val) => exchange_token(
+    let mut client_credentials = None;
+    let token = match method {
+        LoginMethod::RefreshToken(refresh_token_val) => exchange_token(
             auth_url.clone(),
             token_url.clone(),
+            &client,
             &oauth2::RefreshToken::new(refresh_token_val.to_string()),
         )?,
-        None => get_token(auth_url.clone(), token_url.clone())?,
+        LoginMethod::Browser(redirect) => get_token(
+            auth_url.clone(),
+            token_url.clone(),
+            &client,
+            &redirect,
+            prompt,
+        )?,
+        LoginMethod::DeviceCode => get_token_with_device_code(&endpoints, &client, prompt)?,
+        LoginMethod::ClientCredentials(credentials) => {
+            let token = get_token_with_client_credentials(
+                auth_url.clone(),
+                token_url.clone(),
+                &credentials,
+            )?;
+            client_credentials = Some(credentials);
+            token
+        }
+        LoginMethod::AccessToken(token) => {
+            let context = Context {
+                name: context_name,
+                drogue_cloud_url: api_endpoint,
+                default_app: None,
+                token: Some(Token::AccessToken(token)),
+                token_url,
+                auth_url,
+                registry_url,
+                token_exp_date: None,
+                client_credentials: None,
+                oauth_client: None,
+            };
+            // nothing is exchanged for an access token, check it is accepted before saving it.
+            TokenClient::new(&context)
+                .list_tokens()
+                .context("Unable to authenticate with the access token")?;
+            return Ok(context);
+        }
     };
 
     let token_exp_date = calculate_token_expiration_date(&token)?;
@@ -48,70 +173,60 @@ pub fn login(
         name: context_name,
         drogue_cloud_url: api_endpoint,
         default_app: None,
-        token,
+        token: Some(Token::TokenResponse(token)),
         token_url,
         auth_url,
         registry_url,
-        token_exp_date,
+        token_exp_date: Some(token_exp_date),
+        client_credentials,
+        oauth_client,
     };
 
     Ok(config)
 }
 
-fn get_token(auth_url: Url, token_url: Url) -> Result<BasicTokenResponse> {
+fn get_token(
+    auth_url: Url,
+    token_url: Url,
+    oauth_client: &OAuthClient,
+    redirect: &RedirectAddress,
+    prompt: &dyn Fn(LoginPrompt),
+) -> Result<BasicTokenResponse> {
     log::debug!("Using auth url : {}", auth_url);
 
     //start a local server
-    let bind = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
-    let server = Server::http(bind).unwrap();
+    let server = Server::http(redirect.bind_address()?)
+        .map_err(|e| anyhow!("Cannot start a local server for the login redirect: {}", e))?;
     let port = server.server_addr().port();
 
-    let client = BasicClient::new(
-        ClientId::new(CLIENT_ID.to_string()),
-        None,
-        AuthUrl::new(auth_url.to_string())?,
-        Some(TokenUrl::new(token_url.to_string())?),
-    )
-    // Where the user will be redirected to after the authorization process.
-    .set_redirect_url(RedirectUrl::new(format!("http://localhost:{}", port))?);
+    let client = basic_client(oauth_client, auth_url, token_url.clone())?
+        // Where the user will be redirected to after the authorization process.
+        .set_redirect_url(RedirectUrl::new(format!(
+            "http://{}:{}",
+            redirect.host, port
+        ))?);
 
     // Generate a PKCE challenge. As this is a client app a PKCE challenge this is needed to assure confidentiality.
     let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
 
     // Generate the full authorization URL.
-    let (final_auth_url, csrf_token) = client
-        .authorize_url(CsrfToken::new_random)
-        .add_scope(Scope::new("offline_access".to_string()))
-        .set_pkce_challenge(pkce_challenge)
-        .url();
+    let mut request = client.authorize_url(CsrfToken::new_random);
+    for scope in scopes(oauth_client) {
+        request = request.add_scope(Scope::new(scope));
+    }
+    let (final_auth_url, csrf_token) = request.set_pkce_challenge(pkce_challenge).url();
 
     // The URL the user should browse to, in order to trigger the authorization process.
     log::info!("Opening browser.");
     if webbrowser::open(final_auth_url.as_str()).is_err() {
         log::warn!("Failed to open browser.");
-        println!(
-            "\nTo authenticate with drogue cloud please browse to: \n{}",
-            final_auth_url
-        );
+        prompt(LoginPrompt::Browse(&final_auth_url));
     }
 
-    // get the request from the localhost webserver
-    let request = server.recv()?;
-
-    // extract code and state from the openID server request
-    let querry = QString::from(request.url().trim_start_matches("/?"));
-    let state = querry.get("state").unwrap();
-    let code = querry.get("code").unwrap();
-
-    let _ = request.respond(Response::from_string(
-        "Authentication code retrieved. This browser can be closed.",
-    ));
+    let code = wait_for_code(&server, csrf_token.secret())?;
     log::info!("Authentication code retrieved.");
     log::debug!("Trading auth code with token using url : {}", token_url);
 
-    // For security reasons, verify that the `state` parameter returned by the server matches `csrf_state`.
-    assert_eq!(csrf_token.secret().as_str(), state);
-
     // Now trade it for an access token.
     let token_result = client
         .exchange_code(AuthorizationCode::new(code.to_string()))
@@ -123,53 +238,332 @@ fn get_token(auth_url: Url, token_url: Url) -> Result<BasicTokenResponse> {
     token_result.map_err(|_| Error::msg("error retrieving the authentication token"))
 }
 
+/// A request received by the local server during the login.
+enum Redirect {
+    Code(String),
+    Error(Error),
+    // e.g. the browser asking for a favicon.
+    Ignored,
+}
+
+// Wait for the openID server to redirect the browser with an authorization code.
+fn wait_for_code(server: &Server, csrf_state: &str) -> Result<String> {
+    let deadline = Instant::now() + LOGIN_TIMEOUT;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let request = server.recv_timeout(remaining)?.ok_or_else(|| {
+            anyhow!(
+                "Timed out after {} seconds waiting for the login to complete in the browser",
+                LOGIN_TIMEOUT.as_secs()
+            )
+        })?;
+
+        match parse_redirect(request.url(), csrf_state) {
+            Redirect::Code(code) => {
+                let _ = request.respond(html_page(
+                    200,
+                    "Authentication code retrieved. This browser can be closed.",
+                ));
+                return Ok(code);
+            }
+            Redirect::Error(e) => {
+                let _ = request.respond(html_page(400, &format!("Authentication failed. {}", e)));
+                return Err(e);
+            }
+            Redirect::Ignored => {
+                log::debug!("Ignoring request to {}", request.url());
+                let _ = request.respond(Response::empty(404));
+            }
+        }
+    }
+}
+
+fn parse_redirect(url: &str, csrf_state: &str) -> Redirect {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    if path != "/" {
+        return Redirect::Ignored;
+    }
+    let query = QString::from(query);
+
+    // For security reasons, verify that the `state` parameter returned by the server matches `csrf_state`.
+    // Other requests don't come from this login, and must not end it.
+    if query.get("state") != Some(csrf_state) {
+        log::warn!("Ignoring a login redirect without the expected state");
+        return Redirect::Ignored;
+    }
+
+    // e.g. the user denied the access or cancelled the login.
+    if let Some(error) = query.get("error") {
+        let description = query
+            .get("error_description")
+            .map(|d| format!(": {}", d))
+            .unwrap_or_default();
+        return Redirect::Error(anyhow!(
+            "The authentication server returned an error: {}{}",
+            error,
+            description
+        ));
+    }
+
+    match query.get("code") {
+        Some(code) => Redirect::Code(code.to_string()),
+        None => Redirect::Ignored,
+    }
+}
+
+fn html_page(status: u16, message: &str) -> Response<Cursor<Vec<u8>>> {
+    let message = message
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    let body = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>drg</title></head><body><h3>{}</h3></body></html>",
+        message
+    );
+
+    Response::from_data(body)
+        .with_status_code(status)
+        .with_header(
+            Header::from_bytes(&b"Content-Type"[..], &b"text/html; charset=utf-8"[..]).unwrap(),
+        )
+}
+
+#[derive(Deserialize)]
+struct DeviceAuthorization {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    verification_uri_complete: Option<String>,
+    expires_in: u64,
+    interval: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct OAuthError {
+    error: String,
+    error_description: Option<String>,
+}
+
+// OAuth2 device authorization grant, see RFC 8628.
+// The user authenticates on another device while we poll the token endpoint.
+fn get_token_with_device_code(
+    endpoints: &endpoints::OpenIdEndpoints,
+    oauth_client: &OAuthClient,
+    prompt: &dyn Fn(LoginPrompt),
+) -> Result<BasicTokenResponse> {
+    let device_url = endpoints.device_authorization_url.as_ref().ok_or_else(|| {
+        Error::msg("The authentication server does not support the device code flow")
+    })?;
+    log::debug!("Using device authorization url : {}", device_url);
+
+    let client = reqwest::blocking::Client::new();
+    let scope = scopes(oauth_client).collect::<Vec<String>>().join(" ");
+    let authorization: DeviceAuthorization = authenticate_client(
+        client.post(device_url.clone()),
+        vec![("scope", &scope)],
+        oauth_client,
+    )
+    .send()?
+    .error_for_status()
+    .context("Device authorization request failed")?
+    .json()
+    .context("Invalid device authorization response")?;
+
+    prompt(LoginPrompt::DeviceCode {
+        verification_uri: &authorization.verification_uri,
+        user_code: &authorization.user_code,
+        verification_uri_complete: authorization.verification_uri_complete.as_deref(),
+    });
+
+    let deadline = Instant::now() + StdDuration::from_secs(authorization.expires_in);
+    let mut interval = StdDuration::from_secs(authorization.interval.unwrap_or(5));
+
+    loop {
+        if Instant::now() > deadline {
+            return Err(Error::msg(
+                "The device code expired before the login was completed",
+            ));
+        }
+        sleep(interval);
+
+        let res = authenticate_client(
+            client.post(endpoints.token_url.clone()),
+            vec![
+                ("grant_type", DEVICE_CODE_GRANT),
+                ("device_code", &authorization.device_code),
+            ],
+            oauth_client,
+        )
+        .send()?;
+
+        if res.status().is_success() {
+            return res.json().context("Invalid token response");
+        }
+
+        let error: OAuthError = res.json().context("Invalid token error response")?;
+        match error.error.as_str() {
+            "authorization_pending" => log::debug!("Waiting for the user to log in."),
+            "slow_down" => interval += StdDuration::from_secs(5),
+            "access_denied" => return Err(Error::msg("The login request was denied")),
+            "expired_token" => {
+                return Err(Error::msg(
+                    "The device code expired before the login was completed",
+                ))
+            }
+            other => {
+                return Err(anyhow!(
+                    "Error retrieving the authentication token: {} {}",
+                    other,
+                    error.error_description.unwrap_or_default()
+                ))
+            }
+        }
+    }
+}
+
 pub fn verify_token_validity(context: &mut Context) -> Result<bool> {
-    log::debug!("Token expires at : {}", context.token_exp_date);
-    // 30 seconds should be enough
-    if context.token_exp_date - Utc::now() > Duration::seconds(30) {
-        Ok(false)
-    } else {
-        log::info!("Token is expired or will be soon, refreshing...");
-        refresh_token(context)
+    let token = match &context.token {
+        Some(Token::TokenResponse(token)) => token,
+        // access tokens don't expire.
+        Some(Token::AccessToken(_)) => return Ok(false),
+        None => {
+            return Err(anyhow!(
+                "Not logged in with the context {}, please use drg login.",
+                context.name
+            ))
+        }
+    };
+
+    match context.token_exp_date {
+        // 30 seconds should be enough
+        Some(exp_date) if exp_date - Utc::now() > Duration::seconds(30) => {
+            log::debug!("Token expires at : {}", exp_date);
+            Ok(false)
+        }
+        _ => {
+            log::info!("Token is expired or will be soon, refreshing...");
+            let new_token = refresh_token(context, token)?;
+            let token_exp_date = calculate_token_expiration_date(&new_token)?;
+
+            log::info!("New token will expire at {}", token_exp_date);
+            log::info!("Token successfully refreshed.");
+
+            context.token_exp_date = Some(token_exp_date);
+            context.token = Some(Token::TokenResponse(new_token));
+            Ok(true)
+        }
     }
 }
 
-fn refresh_token(context: &mut Context) -> Result<bool> {
-    let refresh_token_var = context
-        .token
-        .refresh_token()
-        .ok_or_else(|| Error::msg("Error loading refresh token from config"))?;
-    let new_token = exchange_token(
-        context.auth_url.clone(),
-        context.token_url.clone(),
-        &refresh_token_var,
-    )?;
+/// End the session of the context on the authentication server and clear its token.
+pub fn logout(context: &mut Context) -> Result<LogoutOutcome> {
+    let outcome = match context.token.take() {
+        Some(Token::TokenResponse(token)) => revoke_token(context, &token)?,
+        Some(Token::AccessToken(_)) => LogoutOutcome::AccessTokenRemoved,
+        None => LogoutOutcome::NotLoggedIn,
+    };
+    context.token_exp_date = None;
+    Ok(outcome)
+}
+
+// Prefer the revocation endpoint and fallback to the end-session endpoint.
+fn revoke_token(context: &Context, token: &BasicTokenResponse) -> Result<LogoutOutcome> {
+    let (sso_url, _) = endpoints::get_drogue_services_endpoint(context.drogue_cloud_url.clone())?;
+    let endpoints = endpoints::get_openid_endpoints(sso_url)?;
+
+    // revoking the refresh token also invalidates the access tokens issued with it.
+    let (token, hint) = match token.refresh_token() {
+        Some(refresh) => (refresh.secret().as_str(), "refresh_token"),
+        None => (token.access_token().secret().as_str(), "access_token"),
+    };
+
+    let (url, form) = match (endpoints.revocation_url, endpoints.end_session_url) {
+        (Some(url), _) => (url, vec![("token", token), ("token_type_hint", hint)]),
+        (None, Some(url)) if hint == "refresh_token" => (url, vec![("refresh_token", token)]),
+        _ => return Ok(LogoutOutcome::NotRevoked),
+    };
+    log::debug!("Revoking token using url : {}", url);
+
+    let oauth_client = match &context.client_credentials {
+        Some(credentials) => OAuthClient {
+            client_id: credentials.client_id.clone(),
+            client_secret: Some(credentials.client_secret.clone()),
+            scopes: Vec::new(),
+        },
+        None => context.oauth_client.clone().unwrap_or_default(),
+    };
 
-    context.token_exp_date = calculate_token_expiration_date(&new_token)?;
-    context.token = new_token;
+    authenticate_client(
+        reqwest::blocking::Client::new().post(url),
+        form,
+        &oauth_client,
+    )
+    .send()?
+    .error_for_status()
+    .context("Error revoking the token")?;
+    log::info!("Token successfully revoked.");
+    Ok(LogoutOutcome::Revoked)
+}
+
+fn refresh_token(context: &Context, token: &BasicTokenResponse) -> Result<BasicTokenResponse> {
+    match &context.client_credentials {
+        // there is no refresh token with the client credentials grant, ask for a new token.
+        Some(credentials) => get_token_with_client_credentials(
+            context.auth_url.clone(),
+            context.token_url.clone(),
+            credentials,
+        ),
+        None => {
+            let refresh_token_var = token
+                .refresh_token()
+                .ok_or_else(|| Error::msg("Error loading refresh token from config"))?;
+            exchange_token(
+                context.auth_url.clone(),
+                context.token_url.clone(),
+                &context.oauth_client.clone().unwrap_or_default(),
+                refresh_token_var,
+            )
+        }
+    }
+}
+
+fn get_token_with_client_credentials(
+    auth_url: Url,
+    token_url: Url,
+    credentials: &ClientCredentials,
+) -> Result<BasicTokenResponse> {
+    log::debug!(
+        "Requesting a token for client {} using url : {}",
+        credentials.client_id,
+        &token_url
+    );
 
-    log::info!("New token will expire at {}", context.token_exp_date);
-    log::info!("Token successfully refreshed.");
+    let client = BasicClient::new(
+        ClientId::new(credentials.client_id.clone()),
+        Some(ClientSecret::new(credentials.client_secret.clone())),
+        AuthUrl::new(auth_url.to_string())?,
+        Some(TokenUrl::new(token_url.to_string())?),
+    );
 
-    Ok(true)
+    client
+        .exchange_client_credentials()
+        .request(http_client)
+        .map_err(|e| {
+            log::debug!("{:?}", e);
+            Error::msg("Error retrieving a token with the client credentials")
+        })
 }
 
 fn exchange_token(
     auth_url: Url,
     token_url: Url,
+    oauth_client: &OAuthClient,
     refresh_token_val: &oauth2::RefreshToken,
 ) -> Result<BasicTokenResponse> {
     log::debug!("Refreshing token using url : {}", &token_url);
 
-    let auth_url = AuthUrl::new(auth_url.to_string())?;
-    let token_url = TokenUrl::new(token_url.to_string())?;
-
-    let client = BasicClient::new(
-        ClientId::new(CLIENT_ID.to_string()),
-        None,
-        auth_url,
-        Some(token_url),
-    );
+    let client = basic_client(oauth_client, auth_url, token_url)?;
 
     // Exchange the refresh token for access token
     client
@@ -178,6 +572,70 @@ fn exchange_token(
         .map_err(|_| Error::msg("Invalid refresh token"))
 }
 
+fn basic_client(oauth_client: &OAuthClient, auth_url: Url, token_url: Url) -> Result<BasicClient> {
+    Ok(BasicClient::new(
+        ClientId::new(oauth_client.client_id.clone()),
+        oauth_client.client_secret.clone().map(ClientSecret::new),
+        AuthUrl::new(auth_url.to_string())?,
+        Some(TokenUrl::new(token_url.to_string())?),
+    ))
+}
+
+// offline_access is needed to get a refresh token.
+fn scopes(oauth_client: &OAuthClient) -> impl Iterator<Item = String> + '_ {
+    std::iter::once("offline_access".to_string()).chain(oauth_client.scopes.iter().cloned())
+}
+
+// Send the form to the authentication server, as a public or a confidential client.
+fn authenticate_client<'a>(
+    req: RequestBuilder,
+    mut form: Vec<(&'a str, &'a str)>,
+    oauth_client: &'a OAuthClient,
+) -> RequestBuilder {
+    match &oauth_client.client_secret {
+        Some(secret) => req.basic_auth(&oauth_client.client_id, Some(secret)),
+        None => {
+            form.push(("client_id", &oauth_client.client_id));
+            req
+        }
+    }
+    .form(&form)
+}
+
+/// The claims of an access token shown by `whoami`.
+#[derive(Debug, Default, Deserialize)]
+pub struct Claims {
+    pub sub: Option<String>,
+    pub iss: Option<String>,
+    pub preferred_username: Option<String>,
+    pub email: Option<String>,
+    /// Space separated list of the granted scopes.
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub realm_access: Roles,
+    /// The client roles, by client id.
+    #[serde(default)]
+    pub resource_access: BTreeMap<String, Roles>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Roles {
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+/// Decode the claims of a JWT, the signature is not verified.
+pub fn decode_claims(token: &str) -> Result<Claims> {
+    let payload = token
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| Error::msg("The access token is not a JWT"))?;
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD)
+        .context("Invalid encoding of the access token claims")?;
+
+    serde_json::from_slice(&payload).context("Invalid access token claims")
+}
+
 fn calculate_token_expiration_date(token: &BasicTokenResponse) -> Result<DateTime<Utc>> {
     let now = Utc::now();
     let expiration = token
@@ -188,16 +646,82 @@ fn calculate_token_expiration_date(token: &BasicTokenResponse) -> Result<DateTim
         .ok_or_else(|| anyhow::Error::msg("Error calculating token expiration date"))
 }
 
-pub fn print_token(context: &Context) {
-    println!("{}", context.token.access_token().secret());
-}
-pub fn print_whoami(context: &Context) {
-    println!("Cluster adress : {}", context.drogue_cloud_url);
-    println!(
-        "Default App : {}",
-        context
-            .default_app
-            .as_ref()
-            .unwrap_or(&"No default app".to_string())
-    );
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATE: &str = "csrf-state";
+
+    #[test]
+    fn redirect_bind_address() -> Result<()> {
+        let address = |host: &str| RedirectAddress {
+            host: host.to_string(),
+            port: 8085,
+        };
+
+        assert_eq!(
+            address("localhost").bind_address()?,
+            "127.0.0.1:8085".parse()?
+        );
+        assert_eq!(
+            address("127.0.0.2").bind_address()?,
+            "127.0.0.2:8085".parse()?
+        );
+        assert!(address("host.invalid").bind_address().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn redirect_with_code() {
+        match parse_redirect("/?state=csrf-state&code=abc", STATE) {
+            Redirect::Code(code) => assert_eq!(code, "abc"),
+            _ => panic!("the code was not found"),
+        }
+    }
+
+    #[test]
+    fn redirect_with_error() {
+        match parse_redirect(
+            "/?state=csrf-state&error=access_denied&error_description=denied",
+            STATE,
+        ) {
+            Redirect::Error(e) => assert_eq!(
+                e.to_string(),
+                "The authentication server returned an error: access_denied: denied"
+            ),
+            _ => panic!("the error was not found"),
+        }
+    }
+
+    #[test]
+    fn redirect_with_another_state() {
+        for url in &[
+            "/?state=other&code=abc",
+            "/?code=abc",
+            "/?state=other&error=access_denied",
+            "/?error=x",
+        ] {
+            assert!(
+                matches!(parse_redirect(url, STATE), Redirect::Ignored),
+                "{} was not ignored",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn stray_requests() {
+        for url in &[
+            "/favicon.ico",
+            "/favicon.ico?state=csrf-state&code=abc",
+            "/",
+            "/?state=csrf-state",
+        ] {
+            assert!(
+                matches!(parse_redirect(url, STATE), Redirect::Ignored),
+                "{} was not ignored",
+                url
+            );
+        }
+    }
 }
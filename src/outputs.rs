@@ -0,0 +1,185 @@
+use crate::models::{ObjectMeta, Resource, Status};
+use crate::util;
+use anyhow::{anyhow, Result};
+use jsonpath_lib::Compiled;
+use serde::Serialize;
+use serde_json::Value;
+use std::str::FromStr;
+use tinytemplate::{format_unescaped, TinyTemplate};
+
+const TEMPLATE_NAME: &str = "output";
+
+#[derive(Clone, PartialEq)]
+pub enum OutputFormat {
+    // colored JSON for a single resource, a table for lists.
+    Default,
+    Json,
+    Yaml,
+    Wide,
+    Name,
+    JsonPath(String),
+    Template(String),
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once('=') {
+            Some(("jsonpath", path)) => {
+                let path = normalize_jsonpath(path);
+                Compiled::compile(&path)
+                    .map_err(|e| anyhow!("Invalid JSONPath expression '{}': {}", path, e))?;
+                Ok(OutputFormat::JsonPath(path))
+            }
+            Some(("template", template)) => {
+                TinyTemplate::new()
+                    .add_template(TEMPLATE_NAME, template)
+                    .map_err(|e| anyhow!("Invalid template: {}", e))?;
+                Ok(OutputFormat::Template(template.to_string()))
+            }
+            _ => match s {
+                "json" => Ok(OutputFormat::Json),
+                "yaml" => Ok(OutputFormat::Yaml),
+                "wide" => Ok(OutputFormat::Wide),
+                "name" => Ok(OutputFormat::Name),
+                other => Err(anyhow!(
+                    "Invalid output format '{}'. Supported formats are json, yaml, wide, name, jsonpath=<expression> and template=<template>.",
+                    other
+                )),
+            },
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn from(value: Option<&str>) -> Result<OutputFormat> {
+        value.map_or(Ok(OutputFormat::Default), OutputFormat::from_str)
+    }
+}
+
+// Also accept the kubectl flavour of JSONPath: `{.metadata.name}`
+fn normalize_jsonpath(path: &str) -> String {
+    let path = path
+        .strip_prefix('{')
+        .and_then(|p| p.strip_suffix('}'))
+        .unwrap_or(path);
+    if path.starts_with('$') {
+        path.to_string()
+    } else if path.starts_with('.') || path.starts_with('[') {
+        format!("${}", path)
+    } else {
+        format!("$.{}", path)
+    }
+}
+
+/// Prints a table of resources, the boolean asks for the wide version of the table.
+pub type TablePrinter<T> = fn(&[T], bool) -> Result<()>;
+
+pub fn show_object<T>(data: T, format: OutputFormat, table: TablePrinter<T>) -> Result<()>
+where
+    T: Resource + Serialize,
+{
+    match format {
+        OutputFormat::Default | OutputFormat::Json => {
+            util::show_json(serde_json::to_string(&data)?)
+        }
+        OutputFormat::Yaml => print!("{}", serde_yaml::to_string(&data)?),
+        OutputFormat::Wide => table(&[data], true)?,
+        OutputFormat::Name => println!("{}", data.metadata().name),
+        OutputFormat::JsonPath(path) => print_jsonpath(&[serde_json::to_value(&data)?], &path)?,
+        OutputFormat::Template(template) => {
+            print_template(&[serde_json::to_value(&data)?], &template)?
+        }
+    }
+    Ok(())
+}
+
+pub fn show_list<T>(data: Vec<T>, format: OutputFormat, table: TablePrinter<T>) -> Result<()>
+where
+    T: Resource + Serialize,
+{
+    match format {
+        OutputFormat::Default => table(&data, false)?,
+        OutputFormat::Wide => table(&data, true)?,
+        OutputFormat::Json => util::show_json(serde_json::to_string(&data)?),
+        OutputFormat::Yaml => print!("{}", serde_yaml::to_string(&data)?),
+        OutputFormat::Name => data.iter().for_each(|d| println!("{}", d.metadata().name)),
+        OutputFormat::JsonPath(path) => print_jsonpath(&to_values(&data)?, &path)?,
+        OutputFormat::Template(template) => print_template(&to_values(&data)?, &template)?,
+    }
+    Ok(())
+}
+
+pub fn to_values<T: Serialize>(data: &[T]) -> Result<Vec<Value>> {
+    data.iter()
+        .map(|d| serde_json::to_value(d).map_err(anyhow::Error::from))
+        .collect()
+}
+
+// One line per resource, multiple matches are separated with a space.
+pub fn print_jsonpath(data: &[Value], path: &str) -> Result<()> {
+    let path = Compiled::compile(path).map_err(|e| anyhow!("Invalid JSONPath: {}", e))?;
+
+    for item in data {
+        let matches = path
+            .select(item)
+            .map_err(|e| anyhow!("Cannot evaluate JSONPath: {}", e))?;
+        let line = matches
+            .iter()
+            .map(|v| match v {
+                Value::String(s) => s.clone(),
+                v => v.to_string(),
+            })
+            .collect::<Vec<String>>()
+            .join(" ");
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+// The template is rendered once per resource.
+pub fn print_template(data: &[Value], template: &str) -> Result<()> {
+    let mut tt = TinyTemplate::new();
+    tt.set_default_formatter(&format_unescaped);
+    tt.add_template(TEMPLATE_NAME, template)?;
+
+    for item in data {
+        println!("{}", tt.render(TEMPLATE_NAME, item)?);
+    }
+    Ok(())
+}
+
+// The following helpers render the columns of the wide tables.
+
+pub fn labels_column(metadata: &ObjectMeta) -> String {
+    let labels = metadata
+        .labels
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<String>>()
+        .join(",");
+    none_if_empty(labels).unwrap_or_else(|| "<none>".to_string())
+}
+
+pub fn conditions_column(status: &Option<Status>) -> String {
+    status
+        .as_ref()
+        .map(|s| {
+            s.conditions
+                .iter()
+                .map(|c| format!("{}={}", c.condition_type, c.status))
+                .collect::<Vec<String>>()
+                .join(",")
+        })
+        .and_then(none_if_empty)
+        .unwrap_or_else(|| "<none>".to_string())
+}
+
+pub fn none_if_empty(s: String) -> Option<String> {
+    if s.is_empty() {
+        None
+    } else {
+        Some(s)
+    }
+}
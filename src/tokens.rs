@@ -0,0 +1,85 @@
+use crate::client::TokenClient;
+use crate::config::Context;
+use crate::models::AccessTokenInfo;
+use crate::outputs::{self, OutputFormat};
+use crate::util;
+use anyhow::{anyhow, Result};
+use tabular::{Row, Table};
+
+pub fn create(config: &Context, description: Option<&str>, output: OutputFormat) -> Result<()> {
+    // checked first, the secret of the token can't be shown again.
+    if !matches!(
+        output,
+        OutputFormat::Default | OutputFormat::Json | OutputFormat::Yaml
+    ) {
+        return Err(anyhow!(
+            "A new token can only be shown with the default, json and yaml outputs."
+        ));
+    }
+
+    let token = TokenClient::new(config).create_token(description)?;
+
+    match output {
+        OutputFormat::Json => util::show_json(serde_json::to_string(&token)?),
+        OutputFormat::Yaml => print!("{}", serde_yaml::to_string(&token)?),
+        _ => {
+            println!(
+                "A new access token was created with the prefix {}:",
+                token.prefix
+            );
+            println!("{}", token.token);
+            println!("Make sure to save it, it won't be shown again.");
+        }
+    }
+    Ok(())
+}
+
+pub fn list(config: &Context, output: OutputFormat) -> Result<()> {
+    let tokens = TokenClient::new(config).list_tokens()?;
+
+    match output {
+        OutputFormat::Default => pretty_list(&tokens),
+        OutputFormat::Json => util::show_json(serde_json::to_string(&tokens)?),
+        OutputFormat::Yaml => print!("{}", serde_yaml::to_string(&tokens)?),
+        OutputFormat::Name => tokens.iter().for_each(|t| println!("{}", t.prefix)),
+        OutputFormat::JsonPath(path) => {
+            outputs::print_jsonpath(&outputs::to_values(&tokens)?, &path)?
+        }
+        OutputFormat::Template(template) => {
+            outputs::print_template(&outputs::to_values(&tokens)?, &template)?
+        }
+        OutputFormat::Wide => {
+            return Err(anyhow!(
+                "Access tokens can't be shown with the wide output."
+            ))
+        }
+    }
+    Ok(())
+}
+
+pub fn delete(config: &Context, prefix: &str) -> Result<()> {
+    TokenClient::new(config).delete_token(prefix)?;
+    println!("Access token {} deleted.", prefix);
+    Ok(())
+}
+
+fn pretty_list(tokens: &[AccessTokenInfo]) {
+    let mut table = Table::new("{:<} {:<} {:<}");
+    table.add_row(
+        Row::new()
+            .with_cell("PREFIX")
+            .with_cell("CREATED")
+            .with_cell("DESCRIPTION"),
+    );
+
+    for token in tokens {
+        table.add_row(
+            Row::new()
+                .with_cell(&token.prefix)
+                .with_cell(token.created.format("%Y-%m-%d %H:%M:%S"))
+                .with_cell(token.description.as_deref().unwrap_or("<none>")),
+        );
+    }
+
+    print!("{}", table);
+}
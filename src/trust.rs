@@ -1,3 +1,4 @@
+use crate::models::TrustAnchor;
 use anyhow::{anyhow, Result};
 use base64::encode;
 use chrono::{Duration, Utc};
@@ -5,7 +6,6 @@ use rcgen::{
     BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa,
     KeyIdMethod, KeyPair,
 };
-use serde_json::{json, Value};
 use std::fs::File;
 use std::io::Write;
 use std::{fs, process::exit, str::from_utf8};
@@ -67,7 +67,7 @@ pub fn create_trust_anchor(
     app_id: &str,
     keyout: Option<&str>,
     days: Option<&str>,
-) -> Result<Value> {
+) -> Result<TrustAnchor> {
     const OU: &str = "Cloud";
     let app_certificate = generate_certificate(CertificateType::app, app_id, OU, days)?;
 
@@ -79,22 +79,17 @@ pub fn create_trust_anchor(
 
     // Private key printed to terminal, when keyout argument not specified.
     match keyout {
-        Some(file_name) => write_to_file(file_name, &private_key, "App private key"),
+        Some(file_name) => write_to_file(file_name, private_key, "App private key"),
         _ => {
             println!("Private key for an application is used to sign device certificates, see `drg trust add --help`\n");
             println!("{}", &private_key)
         }
     };
 
-    Ok(json!({
-        "trustAnchors": {
-            "anchors": [
-                {
-                    "certificate": encode(pem_cert)
-                }
-            ]
-        }
-    }))
+    Ok(TrustAnchor {
+        certificate: encode(pem_cert),
+        extra: Default::default(),
+    })
 }
 
 pub fn create_device_certificate(
@@ -109,13 +104,13 @@ pub fn create_device_certificate(
     let ca_key_content = KeyPair::from_pem(&read_from_file(ca_key))
         .map_err(|e| anyhow!("Error reading CA key file. {}", e))?;
 
-    let ca_base64 = base64::decode(&ca_cert)?;
+    let ca_base64 = base64::decode(ca_cert)?;
     let ca_cert_pem = from_utf8(&ca_base64)?;
 
-    let ca_certificate = CertificateParams::from_ca_cert_pem(&ca_cert_pem, ca_key_content)
+    let ca_certificate = CertificateParams::from_ca_cert_pem(ca_cert_pem, ca_key_content)
         .map_err(|e| anyhow!("Error: {}", e))?;
 
-    let device_csr = generate_certificate(CertificateType::device, &device_id, &app_id, days)?;
+    let device_csr = generate_certificate(CertificateType::device, device_id, app_id, days)?;
     let ca_cert_fin = Certificate::from_params(ca_certificate)?;
 
     // Signing the device certificate with CA
@@ -147,7 +142,7 @@ pub fn create_device_certificate(
 fn write_to_file(file_name: &str, content: &str, resource_type: &str) {
     let mut file = File::create(file_name);
     match file.as_mut() {
-        Ok(file) => match file.write_all(&content.as_bytes()) {
+        Ok(file) => match file.write_all(content.as_bytes()) {
             Ok(_) => {
                 println!(
                     "{} was successfully written to file {}.",
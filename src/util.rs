@@ -1,71 +1,209 @@
-use crate::config::Config;
-use crate::Verbs;
-use anyhow::{Context as AnyhowContext, Result};
-use chrono::{Duration, Utc};
+use crate::arguments::Other_flags;
+use crate::client::{ClientError, ClientResult};
+use crate::config::{Config, Context, Token};
+use crate::endpoints;
+use crate::models::{ObjectMeta, Resource};
+use crate::openid::{self, LoginPrompt};
+use crate::outputs::{none_if_empty, OutputFormat};
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use chrono::{DateTime, Duration, Utc};
 use clap::crate_version;
 use clap::ArgMatches;
 use colored_json::write_colored_json;
 use log::LevelFilter;
-use reqwest::blocking::{Client, Response};
+use oauth2::TokenResponse;
 use reqwest::StatusCode;
-use serde_json::{from_str, Value};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{from_str, json, Map, Value};
+use std::collections::BTreeMap;
 use std::fs;
-use std::io::stdout;
-use std::io::{Read, Write};
+use std::io::{stdout, Write};
+use std::path::Path;
 use std::process::exit;
+use std::thread::sleep;
+use std::time::Duration as StdDuration;
+use tabular::{Row, Table};
 use tempfile::Builder;
-use url::Url;
 
 pub const VERSION: &str = crate_version!();
 pub const COMPATIBLE_DROGUE_VERSION: &str = "0.5.0";
-pub const API_PATH: &str = "api/registry/v1alpha1";
-
-pub fn print_result(r: Response, resource_name: String, op: Verbs) {
-    match op {
-        Verbs::create => match r.status() {
-            StatusCode::CREATED => println!("{} created.", resource_name),
-            r => exit_with_code(r),
-        },
-        Verbs::delete => match r.status() {
-            StatusCode::NO_CONTENT => println!("{} deleted.", resource_name),
-            r => exit_with_code(r),
-        },
-        Verbs::get => match r.status() {
-            StatusCode::OK => show_json(r.text().expect("Empty response")),
-            r => exit_with_code(r),
-        },
-        Verbs::edit | Verbs::set => match r.status() {
-            StatusCode::NO_CONTENT => println!("{} updated.", resource_name),
-            r => exit_with_code(r),
-        },
-    }
+
+/// Write a secret to a file only readable by the user.
+pub fn write_secret(path: &str, secret: &str) -> Result<()> {
+    let dir = match Path::new(path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    // temporary files are created with restricted permissions.
+    let mut file = Builder::new().tempfile_in(dir)?;
+    writeln!(file, "{}", secret)?;
+    file.persist(path)
+        .context(format!("Unable to write the file {}", path))?;
+    Ok(())
 }
 
-fn show_json<S: Into<String>>(payload: S) {
+pub fn show_json<S: Into<String>>(payload: S) {
     let payload = payload.into();
     match serde_json::from_str(&payload) {
         // show as JSON
         Ok(json) => {
             write_colored_json(&json, &mut stdout().lock()).ok();
+            println!();
         }
         // fall back to plain text output
         Err(_) => println!("{}", payload),
     }
 }
 
-pub fn exit_with_code(r: reqwest::StatusCode) -> ! {
-    log::error!("Error : {}", r);
-    if r.as_u16() == 403 {
-        exit(4)
+// Exit codes, the same for every command. Keep the README in sync.
+pub const EXIT_GENERAL_ERROR: i32 = 1;
+pub const EXIT_SERVER_ERROR: i32 = 2;
+pub const EXIT_NETWORK_ERROR: i32 = 3;
+pub const EXIT_UNAUTHORIZED: i32 = 4;
+pub const EXIT_NOT_FOUND: i32 = 5;
+pub const EXIT_CONFLICT: i32 = 6;
+pub const EXIT_VALIDATION_ERROR: i32 = 7;
+pub const EXIT_DIFFERENCES_FOUND: i32 = 8;
+
+/// Report the error and exit with the matching code.
+/// With `--output json` the error is written to stderr as a JSON object.
+pub fn exit_with_error(error: anyhow::Error, output: &OutputFormat) -> ! {
+    let (code, kind) = classify_error(&error);
+
+    if *output == OutputFormat::Json {
+        let mut report = json!({
+            "error": kind,
+            "message": format!("{:#}", error),
+            "exitCode": code,
+        });
+        match error.chain().find_map(|e| e.downcast_ref::<ClientError>()) {
+            Some(ClientError::Service { status, error }) => {
+                report["error"] = json!(error.error);
+                report["message"] = json!(error.message);
+                report["status"] = json!(status.as_u16());
+            }
+            Some(ClientError::NotFound) => {
+                report["status"] = json!(StatusCode::NOT_FOUND.as_u16());
+            }
+            _ => {}
+        }
+        eprintln!("{}", report);
+    } else {
+        eprintln!("Error: {:?}", error);
     }
-    exit(2)
+
+    exit(code)
 }
 
-pub fn url_validation(url: &str) -> Result<Url> {
-    Url::parse(url).or_else(|_| {
-        Url::parse(&format!("https://{}", url))
-            .context(format!("URL args: \'{}\' is not valid", url))
-    })
+// The exit code and a short error kind, from the first registry or network error found.
+fn classify_error(error: &anyhow::Error) -> (i32, &'static str) {
+    for cause in error.chain() {
+        if let Some(e) = cause.downcast_ref::<ClientError>() {
+            return match e {
+                ClientError::Request(_) => (EXIT_NETWORK_ERROR, "NetworkError"),
+                ClientError::Response(_) => (EXIT_SERVER_ERROR, "InvalidResponse"),
+                ClientError::NotFound => (EXIT_NOT_FOUND, "NotFound"),
+                ClientError::Service { status, .. } => match *status {
+                    StatusCode::UNAUTHORIZED => (EXIT_UNAUTHORIZED, "Unauthorized"),
+                    StatusCode::FORBIDDEN => (EXIT_UNAUTHORIZED, "Forbidden"),
+                    StatusCode::CONFLICT => (EXIT_CONFLICT, "Conflict"),
+                    StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => {
+                        (EXIT_VALIDATION_ERROR, "ValidationError")
+                    }
+                    _ => (EXIT_SERVER_ERROR, "ServerError"),
+                },
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            if e.is_connect() || e.is_timeout() {
+                return (EXIT_NETWORK_ERROR, "NetworkError");
+            }
+        }
+    }
+    (EXIT_GENERAL_ERROR, "Error")
+}
+
+/// Poll a list of resources and print the ADDED, MODIFIED and DELETED ones until interrupted.
+/// The token is refreshed when it expires during the watch, the config file is not updated.
+pub fn watch<T, F, S>(
+    context: &Context,
+    fetch: F,
+    save_token: S,
+    output: OutputFormat,
+    interval: StdDuration,
+) -> Result<()>
+where
+    T: Resource + Serialize + PartialEq,
+    F: Fn(&Context) -> ClientResult<Vec<T>>,
+    S: Fn(&Context) -> Result<()>,
+{
+    if output != OutputFormat::Default && output != OutputFormat::Json {
+        return Err(anyhow!(
+            "Watch mode only supports the default and json outputs."
+        ));
+    }
+    if output == OutputFormat::Default {
+        println!("{:<10} {:<40} AGE", "EVENT", "NAME");
+    }
+
+    let mut context = context.clone();
+    let mut known: BTreeMap<String, T> = BTreeMap::new();
+    loop {
+        let list = openid::verify_token_validity(&mut context).and_then(|refreshed| {
+            // the previous refresh token may not be valid anymore for the other drg commands.
+            if refreshed {
+                if let Err(e) = save_token(&context) {
+                    log::error!("Failed to save the refreshed token: {:#}", e);
+                }
+            }
+            Ok(fetch(&context)?)
+        });
+        match list {
+            Ok(list) => {
+                let current: BTreeMap<String, T> = list
+                    .into_iter()
+                    .map(|item| (item.metadata().name.clone(), item))
+                    .collect();
+
+                for (name, item) in &current {
+                    match known.get(name) {
+                        None => print_event("ADDED", item, &output),
+                        Some(previous) if previous != item => {
+                            print_event("MODIFIED", item, &output)
+                        }
+                        _ => {}
+                    }
+                }
+                for (name, item) in &known {
+                    if !current.contains_key(name) {
+                        print_event("DELETED", item, &output);
+                    }
+                }
+
+                known = current;
+            }
+            // polling again won't help with a rejected token.
+            Err(e) if classify_error(&e).0 == EXIT_UNAUTHORIZED => return Err(e),
+            // keep watching, the next poll may succeed.
+            Err(e) => log::error!("Failed to refresh the list: {:#}", e),
+        }
+
+        sleep(interval);
+    }
+}
+
+fn print_event<T: Resource + Serialize>(event: &str, item: &T, output: &OutputFormat) {
+    if *output == OutputFormat::Json {
+        println!("{}", json!({ "type": event, "object": item }));
+    } else {
+        println!(
+            "{:<10} {:<40} {}",
+            event,
+            item.metadata().name,
+            age(item.metadata())
+        );
+    }
 }
 
 pub fn json_parse(data: Option<&str>) -> Result<Value> {
@@ -75,41 +213,240 @@ pub fn json_parse(data: Option<&str>) -> Result<Value> {
     ))
 }
 
-pub fn editor(original: String) -> Result<Value> {
-    let data: Value = serde_json::from_str(original.as_str())?;
-
+const EDIT_HEADER: &str =
+    "Please edit the resource below. Lines beginning with a '#' will be ignored,
+and an empty file will abort the edit. If an error occurs while saving, this file will be
+reopened with the relevant failures.";
+
+/// Edit a resource in a text editor and send it back to the server with `update`.
+/// Returns false if the edit was cancelled.
+///
+/// Like `kubectl edit`, the editor is opened again with the error at the top of the file
+/// until the resource is valid and accepted by the server, or the file is cleared.
+/// If the resource was modified on the server in the meantime, the user's changes are
+/// re-applied to the latest version to be reviewed.
+pub fn edit_resource<T, G, U>(get: G, update: U) -> Result<bool>
+where
+    T: Serialize + DeserializeOwned,
+    G: Fn() -> ClientResult<T>,
+    U: Fn(&T) -> ClientResult<()>,
+{
+    let original = serde_json::to_value(get()?)?;
     let file = Builder::new().suffix(".yml").tempfile()?;
-    //the handler needs to be kept to reopen the file later.
-    let mut file2 = file.reopen()?;
-
-    // Write the original data to the file, but in YAML for easier editing
-    file.as_file()
-        .write_all(serde_yaml::to_string(&data)?.as_bytes())?;
-
-    edit::edit_file(file.path())
-        .map_err(|err| {
-            log::debug!("{}", err);
-            log::error!(
-                "Error opening a text editor, please try using --filename with the following json"
+
+    edit_loop(file.path(), original, &get, &update).inspect_err(|_| {
+        // the changes would be lost with the file, keep it like kubectl does.
+        match file.keep() {
+            Ok((_, path)) => eprintln!(
+                "A copy of your changes has been stored to {}",
+                path.display()
+            ),
+            Err(e) => log::debug!("Failed to keep the edited file: {}", e),
+        }
+    })
+}
+
+fn edit_loop<T, G, U>(path: &Path, mut original: Value, get: &G, update: &U) -> Result<bool>
+where
+    T: Serialize + DeserializeOwned,
+    G: Fn() -> ClientResult<T>,
+    U: Fn(&T) -> ClientResult<()>,
+{
+    // Write the data to the file in YAML for easier editing
+    let mut text = serde_yaml::to_string(&original)?;
+    let mut error: Option<String> = None;
+
+    loop {
+        fs::write(path, edit_file_content(error.as_deref(), &text))?;
+        edit::edit_file(path)
+            .map_err(|err| {
+                log::debug!("{}", err);
+                log::error!(
+                    "Error opening a text editor, please try using --filename with the following json"
+                );
+                show_json(original.to_string());
+                exit(1);
+            })
+            .unwrap();
+
+        // Read the file by its path, editors may replace it rather than write into it.
+        let content = fs::read_to_string(path)?;
+        text = strip_leading_comments(&content);
+
+        if text
+            .lines()
+            .all(|l| l.trim().is_empty() || l.trim_start().starts_with('#'))
+        {
+            println!("Edit cancelled, the file is empty.");
+            return Ok(false);
+        }
+
+        // parse the whole file so the errors point to the right line.
+        let edited: Value = match serde_yaml::from_str(&content) {
+            Ok(edited) => edited,
+            Err(e) => {
+                error = Some(format!("Invalid YAML data: {}", e));
+                continue;
+            }
+        };
+        if edited == original {
+            println!("Edit cancelled, no changes made.");
+            return Ok(false);
+        }
+
+        let data: T = match serde_json::from_value(edited.clone()) {
+            Ok(data) => data,
+            Err(e) => {
+                error = Some(format!("Invalid resource: {}", e));
+                continue;
+            }
+        };
+
+        match update(&data) {
+            Ok(()) => return Ok(true),
+            Err(e) if e.is_conflict() => {
+                let latest = serde_json::to_value(get()?)?;
+                let mut rebased = latest.clone();
+                apply_merge_patch(&mut rebased, &merge_patch(&original, &edited));
+                original = latest;
+                text = serde_yaml::to_string(&rebased)?;
+                error = Some(
+                    "The resource was modified on the server while you were editing it.\n\
+                     Your changes were applied to the latest version, review them and save again."
+                        .to_string(),
+                );
+            }
+            Err(
+                e @ ClientError::Service {
+                    status: StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY,
+                    ..
+                },
+            ) => {
+                error = Some(format!("The server rejected the update: {}", e));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+// The header and the error go on top of the file, as comments.
+fn edit_file_content(error: Option<&str>, text: &str) -> String {
+    let mut content = String::new();
+    for line in EDIT_HEADER.lines() {
+        content.push_str(&format!("# {}\n", line));
+    }
+    if let Some(error) = error {
+        content.push_str("#\n");
+        for line in error.lines() {
+            content.push_str(&format!("# {}\n", line));
+        }
+    }
+    content.push_str("#\n");
+    content.push_str(text);
+    content
+}
+
+fn strip_leading_comments(text: &str) -> String {
+    text.lines()
+        .skip_while(|l| l.starts_with('#'))
+        .map(|l| format!("{}\n", l))
+        .collect()
+}
+
+// The JSON merge patch (RFC 7386) turning `original` into `edited`.
+fn merge_patch(original: &Value, edited: &Value) -> Value {
+    match (original, edited) {
+        (Value::Object(original), Value::Object(edited)) => {
+            let mut patch = Map::new();
+            for (key, value) in edited {
+                match original.get(key) {
+                    Some(previous) if previous == value => {}
+                    Some(previous) => {
+                        patch.insert(key.clone(), merge_patch(previous, value));
+                    }
+                    None => {
+                        patch.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+            for key in original.keys() {
+                if !edited.contains_key(key) {
+                    patch.insert(key.clone(), Value::Null);
+                }
+            }
+            Value::Object(patch)
+        }
+        (_, edited) => edited.clone(),
+    }
+}
+
+fn apply_merge_patch(target: &mut Value, patch: &Value) {
+    match (target, patch) {
+        (Value::Object(target), Value::Object(patch)) => {
+            for (key, value) in patch {
+                if value.is_null() {
+                    target.remove(key);
+                } else {
+                    apply_merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+                }
+            }
+        }
+        (target, Value::Object(_)) => {
+            *target = Value::Object(Map::new());
+            apply_merge_patch(target, patch);
+        }
+        (target, patch) => *target = patch.clone(),
+    }
+}
+
+pub fn print_login_prompt(prompt: LoginPrompt) {
+    match prompt {
+        LoginPrompt::Browse(url) => println!(
+            "\nTo authenticate with drogue cloud please browse to: \n{}",
+            url
+        ),
+        LoginPrompt::DeviceCode {
+            verification_uri,
+            user_code,
+            verification_uri_complete,
+        } => {
+            println!(
+                "\nTo authenticate with drogue cloud please browse to: \n{}\nand enter the code: {}",
+                verification_uri, user_code
             );
-            show_json(&original);
-            exit(1);
-        })
-        .unwrap();
-
-    // Read the data using the second handle.
-    let mut buf = String::new();
-    file2.read_to_string(&mut buf)?;
-
-    let new_data: Value = serde_yaml::from_str(buf.as_str()).context("Invalid YAML data.")?;
-    if data == new_data {
-        println!("Edit cancelled, no changes made.");
-        exit(2);
-    } else {
-        Ok(new_data)
+            if let Some(complete) = verification_uri_complete {
+                println!("\nOr browse directly to: \n{}", complete);
+            }
+        }
     }
 }
 
+pub fn print_contexts(config: &Config) {
+    let mut table = Table::new("{:<}  {:<}  {:<}");
+    table.add_row(
+        Row::new()
+            .with_cell("NAME")
+            .with_cell("ADDRESS")
+            .with_cell("DEFAULT APP"),
+    );
+
+    for context in &config.contexts {
+        let name = if config.active_context == context.name {
+            format!("{} *", context.name)
+        } else {
+            context.name.clone()
+        };
+        table.add_row(
+            Row::new()
+                .with_cell(&name)
+                .with_cell(&context.drogue_cloud_url)
+                .with_cell(context.default_app.as_deref().unwrap_or("<Not Set>")),
+        );
+    }
+
+    print!("{}", table);
+}
+
 pub fn print_version(config: &Result<Config>) {
     println!("Drg Version: {}", VERSION);
 
@@ -117,7 +454,7 @@ pub fn print_version(config: &Result<Config>) {
         Ok(cfg) => {
             let context = cfg.get_context(&None);
             match context {
-                Ok(ctx) => match get_drogue_services_version(&ctx.drogue_cloud_url) {
+                Ok(ctx) => match endpoints::get_drogue_services_version(&ctx.drogue_cloud_url) {
                     Ok(cloud_version) => {
                         println!("Connected drogue-cloud service: v{}", cloud_version);
                     }
@@ -142,64 +479,111 @@ pub fn print_version(config: &Result<Config>) {
     exit(0);
 }
 
-// use drogue's well known endpoint to retrieve endpoints.
-pub fn get_drogue_services_endpoint(url: Url) -> Result<(Url, Url)> {
-    let client = Client::new();
-
-    let url = url.join(".well-known/drogue-endpoints")?;
+pub fn print_token(context: &Context) {
+    if let Some(token) = &context.token {
+        println!("{}", token.secret());
+    }
+}
 
-    let res = client
-        .get(url)
-        .send()
-        .context("Can't retrieve drogue endpoints details")?;
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WhoAmI<'a> {
+    context: &'a str,
+    cluster: &'a str,
+    default_app: Option<&'a str>,
+    username: Option<String>,
+    email: Option<String>,
+    subject: Option<String>,
+    issuer: Option<String>,
+    roles: Vec<String>,
+    scopes: Vec<String>,
+    token_expiration: Option<DateTime<Utc>>,
+}
 
-    let endpoints: Value = res
-        .json()
-        .context("Cannot deserialize drogue endpoints details")?;
+pub fn print_whoami(context: &Context, output: &OutputFormat) -> Result<()> {
+    if *output != OutputFormat::Default && *output != OutputFormat::Json {
+        return Err(anyhow!(
+            "Whoami only supports the default and json outputs."
+        ));
+    }
 
-    let sso = endpoints["issuer_url"]
-        .as_str()
-        .context("Missing `issuer_url` in drogue endpoint details")?;
-    let registry = endpoints["registry"]["url"]
-        .as_str()
-        .context("Missing `registry` in drogue endpoint details")?;
+    let mut whoami = WhoAmI {
+        context: &context.name,
+        cluster: context.drogue_cloud_url.as_str(),
+        default_app: context.default_app.as_deref(),
+        username: None,
+        email: None,
+        subject: None,
+        issuer: None,
+        roles: Vec::new(),
+        scopes: Vec::new(),
+        token_expiration: context.token_exp_date,
+    };
+
+    match &context.token {
+        Some(Token::TokenResponse(token)) => {
+            match openid::decode_claims(token.access_token().secret()) {
+                // opaque tokens are valid too, only the context is shown.
+                Err(e) => log::warn!("Unable to read the claims of the token: {:#}", e),
+                Ok(claims) => {
+                    whoami.username = claims.preferred_username;
+                    whoami.email = claims.email;
+                    whoami.subject = claims.sub;
+                    whoami.issuer = claims.iss;
+                    whoami.scopes = claims
+                        .scope
+                        .map(|s| s.split_whitespace().map(|s| s.to_string()).collect())
+                        .unwrap_or_default();
+                    // client roles are prefixed with the client id.
+                    whoami.roles = claims.realm_access.roles;
+                    for (client, roles) in claims.resource_access {
+                        whoami
+                            .roles
+                            .extend(roles.roles.iter().map(|r| format!("{}:{}", client, r)));
+                    }
+                }
+            }
+        }
+        Some(Token::AccessToken(token)) => whoami.username = Some(token.username.clone()),
+        None => {}
+    }
 
-    // a trailing / is needed to append the rest of the path.
-    Ok((
-        url_validation(format!("{}/", sso).as_str())?,
-        url_validation(format!("{}/", registry).as_str())?,
-    ))
-}
+    if *output == OutputFormat::Json {
+        show_json(serde_json::to_string(&whoami)?);
+        return Ok(());
+    }
 
-// use keycloak's well known endpoint to retrieve endpoints.
-// http://keycloakhost:keycloakport/auth/realms/{realm}/.well-known/openid-configuration
-pub fn get_auth_and_tokens_endpoints(issuer_url: Url) -> Result<(Url, Url)> {
-    let client = Client::new();
-
-    let url = issuer_url.join(".well-known/openid-configuration")?;
-    let res = client
-        .get(url)
-        .send()
-        .context("Can't retrieve openid-connect endpoints details")?;
-
-    let endpoints: Value = res
-        .json()
-        .context("Cannot deserialize openid-connect endpoints details")?;
-
-    let auth = endpoints["authorization_endpoint"]
-        .as_str()
-        .context("Missing `authorization_endpoint` in drogue openid-connect configuration")?;
-    let auth_endpoint = url_validation(auth);
-    let token = endpoints["token_endpoint"]
-        .as_str()
-        .context("Missing `token_endpoint` in drogue openid-connect configuration")?;
-    let token_endpoint = url_validation(token);
-
-    Ok((auth_endpoint?, token_endpoint?))
+    let none = || "<none>".to_string();
+    println!("Cluster adress : {}", whoami.cluster);
+    println!(
+        "Default App : {}",
+        whoami.default_app.unwrap_or("No default app")
+    );
+    println!("Context : {}", whoami.context);
+    println!("User : {}", whoami.username.unwrap_or_else(none));
+    println!("Email : {}", whoami.email.unwrap_or_else(none));
+    println!("Subject : {}", whoami.subject.unwrap_or_else(none));
+    println!("Issuer : {}", whoami.issuer.unwrap_or_else(none));
+    println!(
+        "Roles : {}",
+        none_if_empty(whoami.roles.join(", ")).unwrap_or_else(none)
+    );
+    println!(
+        "Scopes : {}",
+        none_if_empty(whoami.scopes.join(" ")).unwrap_or_else(none)
+    );
+    println!(
+        "Token expiration : {}",
+        whoami
+            .token_expiration
+            .map(|d| d.to_rfc3339())
+            .unwrap_or_else(|| "never".to_string())
+    );
+    Ok(())
 }
 
 pub fn log_level(matches: &ArgMatches) -> LevelFilter {
-    match matches.occurrences_of("verbose") {
+    match matches.occurrences_of(Other_flags::verbosity.as_ref()) {
         0 => LevelFilter::Error,
         1 => LevelFilter::Warn,
         2 => LevelFilter::Info,
@@ -207,50 +591,143 @@ pub fn log_level(matches: &ArgMatches) -> LevelFilter {
     }
 }
 
-// use drogue's well known endpoint to retrieve version.
-fn get_drogue_services_version(url: &Url) -> Result<String> {
-    let client = Client::new();
-
-    let url = url.join(".well-known/drogue-version")?;
-
-    let res = client
-        .get(url)
-        .send()
-        .context("Can't retrieve drogue version")?;
-
-    let payload: Value = res
-        .json()
-        .context("Cannot deserialize drogue version payload")?;
-
-    let version = payload["version"]
-        .as_str()
-        .context("Missing `version` in drogue version payload")?;
-
-    Ok(version.to_string())
-}
-
 pub fn get_data_from_file(path: &str) -> Result<Value> {
     let contents = fs::read_to_string(path).context("Something went wrong reading the file")?;
 
     serde_json::from_str(contents.as_str()).context("Invalid JSON in file")
 }
 
-pub fn age(str_timestamp: &str) -> Result<String> {
-    let time = chrono::DateTime::parse_from_rfc3339(str_timestamp)?;
-    let age = Utc::now().naive_utc() - time.naive_utc();
+// The age of a resource, from its creation timestamp.
+pub fn age(metadata: &ObjectMeta) -> String {
+    let time = match metadata.creation_timestamp {
+        Some(time) => time,
+        None => return "<unknown>".to_string(),
+    };
+    let age = Utc::now() - time;
 
     if age > Duration::days(7) {
-        Ok(format!("{}d", age.num_days()))
+        format!("{}d", age.num_days())
     } else if age > Duration::days(3) {
         let hours = age
             .checked_sub(&Duration::days(age.num_days()))
             .unwrap_or_else(|| Duration::hours(0));
-        Ok(format!("{}d{}h", age.num_days(), hours.num_hours()))
+        format!("{}d{}h", age.num_days(), hours.num_hours())
     } else if age > Duration::hours(2) {
-        Ok(format!("{}h", age.num_hours()))
+        format!("{}h", age.num_hours())
     } else if age > Duration::minutes(2) {
-        Ok(format!("{}m", age.num_minutes()))
+        format!("{}m", age.num_minutes())
     } else {
-        Ok(format!("{}s", age.num_seconds()))
+        format!("{}s", age.num_seconds())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The original and edited documents must give the same result when the patch is re-applied.
+    fn assert_round_trip(original: Value, edited: Value, expected_patch: Value) {
+        let patch = merge_patch(&original, &edited);
+        assert_eq!(patch, expected_patch);
+
+        let mut target = original;
+        apply_merge_patch(&mut target, &patch);
+        assert_eq!(target, edited);
+    }
+
+    #[test]
+    fn merge_patch_removed_keys() {
+        assert_round_trip(
+            json!({"a": "b", "c": "d"}),
+            json!({"a": "b"}),
+            json!({"c": null}),
+        );
+    }
+
+    #[test]
+    fn merge_patch_nested_objects() {
+        assert_round_trip(
+            json!({"spec": {"a": 1, "b": {"c": 2, "d": 3}}, "e": 4}),
+            json!({"spec": {"a": 1, "b": {"c": 5}}, "e": 4}),
+            json!({"spec": {"b": {"c": 5, "d": null}}}),
+        );
+    }
+
+    #[test]
+    fn merge_patch_replaced_arrays() {
+        assert_round_trip(
+            json!({"a": [{"b": "c"}, 1]}),
+            json!({"a": [{"b": "d"}]}),
+            json!({"a": [{"b": "d"}]}),
+        );
+    }
+
+    #[test]
+    fn merge_patch_on_changed_document() {
+        let original = json!({"metadata": {"labels": {"a": "1"}}, "spec": {"x": 1}});
+        let edited = json!({"metadata": {"labels": {"a": "2"}}, "spec": {"x": 1}});
+        let patch = merge_patch(&original, &edited);
+
+        let mut latest = json!({"metadata": {"labels": {"a": "1", "b": "1"}}, "spec": {"x": 2}});
+        apply_merge_patch(&mut latest, &patch);
+        assert_eq!(
+            latest,
+            json!({"metadata": {"labels": {"a": "2", "b": "1"}}, "spec": {"x": 2}})
+        );
+    }
+
+    // RFC 7386, appendix A.
+    #[test]
+    fn apply_merge_patch_rfc_examples() {
+        let examples = vec![
+            (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+            (
+                json!({"a": "b"}),
+                json!({"b": "c"}),
+                json!({"a": "b", "b": "c"}),
+            ),
+            (json!({"a": "b"}), json!({"a": null}), json!({})),
+            (
+                json!({"a": "b", "b": "c"}),
+                json!({"a": null}),
+                json!({"b": "c"}),
+            ),
+            (json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "c"}), json!({"a": ["b"]}), json!({"a": ["b"]})),
+            (
+                json!({"a": {"b": "c"}}),
+                json!({"a": {"b": "d", "c": null}}),
+                json!({"a": {"b": "d"}}),
+            ),
+            (
+                json!({"a": [{"b": "c"}]}),
+                json!({"a": [1]}),
+                json!({"a": [1]}),
+            ),
+            (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+            (json!({"a": "b"}), json!(["c"]), json!(["c"])),
+            (json!({"a": "foo"}), json!(null), json!(null)),
+            (json!({"a": "foo"}), json!("bar"), json!("bar")),
+            (
+                json!({"e": null}),
+                json!({"a": 1}),
+                json!({"e": null, "a": 1}),
+            ),
+            (
+                json!([1, 2]),
+                json!({"a": "b", "c": null}),
+                json!({"a": "b"}),
+            ),
+            (
+                json!({}),
+                json!({"a": {"bb": {"ccc": null}}}),
+                json!({"a": {"bb": {}}}),
+            ),
+        ];
+
+        for (mut target, patch, expected) in examples {
+            apply_merge_patch(&mut target, &patch);
+            assert_eq!(target, expected, "patch: {}", patch);
+        }
     }
 }